@@ -0,0 +1,382 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+
+use rand::SeedableRng;
+use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::cache::Cache;
+use crate::fully_associative::FullyAssociativeCache;
+use crate::geometry::{CacheGeometry, Capacity};
+use crate::index::{AddressMapping, IndexFunction};
+use crate::lease_table::{
+    run_trace, run_trace_virtual, run_trace_virtual_predict, RunOutputs, SimRng, Trace,
+};
+use crate::leases::LeaseTable;
+use crate::lru_sim::run_lru_simulation;
+use crate::model::{run_lease_model, run_lru_model, LruModel, ReuseProfile};
+use crate::report::{write_rows, ResultRow, SimResult};
+use crate::virtual_cache::VirtualCache;
+
+/// An experiment, read from a TOML file: every mode is run on every benchmark with every
+/// geometry and seed.
+///
+/// ```toml
+/// modes = ["physical", "virtual", "predict", "lru"]
+/// seeds = [1, 2, 3]
+///
+/// [[benchmarks]]
+/// name = "mvt"
+/// trace = "testInput/mvt_output.txt"
+/// lease_table = "testInput/mvt_output_shel_leases"
+///
+/// [[geometries]]
+/// cache_size = "1KB"
+/// associativity = 4
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentSpec {
+    /// the results table, unless `--output` is given
+    #[serde(default = "default_output")]
+    pub output: String,
+    /// the number of simulations run at once; all cores by default
+    pub threads: Option<usize>,
+    pub modes: Vec<Mode>,
+    #[serde(default = "default_seeds")]
+    pub seeds: Vec<u64>,
+    pub benchmarks: Vec<Benchmark>,
+    #[serde(default = "default_geometries")]
+    pub geometries: Vec<GeometrySpec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Physical,
+    Virtual,
+    Predict,
+    Lru,
+    Aet,
+    Footprint,
+    #[serde(rename = "lease-model")]
+    LeaseModel,
+}
+
+impl Mode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Physical => "physical",
+            Mode::Virtual => "virtual",
+            Mode::Predict => "predict",
+            Mode::Lru => "lru",
+            Mode::Aet => "aet",
+            Mode::Footprint => "footprint",
+            Mode::LeaseModel => "lease-model",
+        }
+    }
+
+    fn uses_leases(&self) -> bool {
+        matches!(
+            self,
+            Mode::Physical | Mode::Virtual | Mode::Predict | Mode::LeaseModel
+        )
+    }
+
+    /// whether the mode draws random numbers, and so is run with every seed
+    fn uses_seed(&self) -> bool {
+        matches!(self, Mode::Physical | Mode::Virtual | Mode::Predict)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Benchmark {
+    pub name: String,
+    pub trace: String,
+    pub lease_table: Option<String>,
+}
+
+/// The cache options of a `physical` or `lru` run; `virtual` only uses the address options.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeometrySpec {
+    pub cache_size: Option<CapacitySpec>,
+    pub associativity: Option<u64>,
+    #[serde(default = "default_offset")]
+    pub offset: u64,
+    pub set: Option<u64>,
+    #[serde(default = "default_index")]
+    pub index: IndexFunction,
+    /// the mask of the mask index function, in hex
+    pub index_mask: Option<String>,
+}
+
+/// A cache size written either as a number of blocks or as a string such as `"32KB"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum CapacitySpec {
+    Blocks(u64),
+    Text(String),
+}
+
+fn default_output() -> String {
+    "results.csv".to_string()
+}
+
+fn default_seeds() -> Vec<u64> {
+    vec![0]
+}
+
+fn default_geometries() -> Vec<GeometrySpec> {
+    vec![GeometrySpec {
+        cache_size: None,
+        associativity: None,
+        offset: default_offset(),
+        set: None,
+        index: default_index(),
+        index_mask: None,
+    }]
+}
+
+fn default_offset() -> u64 {
+    3
+}
+
+fn default_index() -> IndexFunction {
+    IndexFunction::Modulo
+}
+
+impl ExperimentSpec {
+    pub fn load(file_path: &str) -> io::Result<ExperimentSpec> {
+        let text = fs::read_to_string(file_path)?;
+        toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl GeometrySpec {
+    fn resolve(&self, mode: Mode) -> Result<(CacheGeometry, AddressMapping), String> {
+        let capacity = match &self.cache_size {
+            Some(CapacitySpec::Blocks(blocks)) => Some(Capacity::Blocks(*blocks)),
+            Some(CapacitySpec::Text(text)) => Some(text.parse()?),
+            None => None,
+        };
+        let mask = match &self.index_mask {
+            Some(mask) => Some(
+                u64::from_str_radix(mask.trim_start_matches("0x"), 16)
+                    .map_err(|e| format!("invalid index mask '{}': {}", mask, e))?,
+            ),
+            None => None,
+        };
+        // a virtual cache has as many ways as it needs, so only the set count is fixed
+        let geometry = match mode {
+            Mode::Virtual => CacheGeometry::from_params(None, Some(1), self.offset, self.set)?,
+            // the predictions only depend on the block size
+            Mode::Predict | Mode::LeaseModel => {
+                CacheGeometry::from_params(None, Some(1), self.offset, Some(0))?
+            }
+            // the models predict a fully associative cache of the size of the others
+            Mode::Aet | Mode::Footprint => {
+                let cache = CacheGeometry::from_params(
+                    capacity,
+                    self.associativity,
+                    self.offset,
+                    self.set,
+                )?;
+                let capacity = Capacity::Blocks(cache.capacity_blocks());
+                CacheGeometry::from_params(Some(capacity), None, self.offset, Some(0))?
+            }
+            _ => CacheGeometry::from_params(capacity, self.associativity, self.offset, self.set)?,
+        };
+        let mapping = match mode {
+            Mode::Aet | Mode::Footprint | Mode::Predict | Mode::LeaseModel => {
+                AddressMapping::new(IndexFunction::Modulo, &geometry, None)?
+            }
+            _ => AddressMapping::new(self.index, &geometry, mask)?,
+        };
+        Ok((geometry, mapping))
+    }
+}
+
+/// One simulation of the cross-product.
+struct Job<'a> {
+    benchmark: &'a Benchmark,
+    mode: Mode,
+    cache: (CacheGeometry, AddressMapping),
+    seed: Option<u64>,
+}
+
+impl Job<'_> {
+    fn run(&self, table: Option<&LeaseTable>) -> io::Result<SimResult> {
+        let trace = Trace::new(&self.benchmark.trace)?;
+        let rng = SimRng::seed_from_u64(self.seed.unwrap_or_default());
+        let mut outputs = RunOutputs::default();
+
+        Ok(match (self.mode, self.cache, table) {
+            (Mode::Physical, (geometry, mapping), Some(table)) if geometry.sets == 1 => {
+                let cache = FullyAssociativeCache::new(Some(geometry.capacity_blocks()));
+                run_trace(cache, trace, table, &mapping, &rng, &mut outputs)
+            }
+            (Mode::Physical, (geometry, mapping), Some(table)) => {
+                let cache = Cache::new(&geometry, &mapping);
+                run_trace(cache, trace, table, &mapping, &rng, &mut outputs)
+            }
+            (Mode::Virtual, (geometry, mapping), Some(table)) if geometry.sets == 1 => {
+                let cache = FullyAssociativeCache::new(None);
+                run_trace_virtual(cache, trace, table, &mapping, &rng, &mut outputs)
+            }
+            (Mode::Virtual, (geometry, mapping), Some(table)) => {
+                let cache = VirtualCache::new(&geometry);
+                run_trace_virtual(cache, trace, table, &mapping, &rng, &mut outputs)
+            }
+            (Mode::Predict, (geometry, _), Some(table)) => {
+                run_trace_virtual_predict(trace, table, geometry.offset_bits(), &rng, &mut outputs)
+            }
+            (Mode::Lru, (geometry, mapping), _) => run_lru_simulation(trace, &geometry, &mapping),
+            (Mode::Aet, (geometry, _), _) => {
+                let profile = ReuseProfile::new(trace, geometry.offset_bits());
+                run_lru_model(LruModel::Aet, &profile, &geometry)
+            }
+            (Mode::Footprint, (geometry, _), _) => {
+                let profile = ReuseProfile::new(trace, geometry.offset_bits());
+                run_lru_model(LruModel::Footprint, &profile, &geometry)
+            }
+            (Mode::LeaseModel, (geometry, _), Some(table)) => {
+                run_lease_model(trace, table, geometry.offset_bits())?
+            }
+            _ => unreachable!("jobs are built with the inputs their mode needs"),
+        })
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!("{} {}", self.benchmark.name, self.mode.name());
+        let (geometry, mapping) = &self.cache;
+        description += &format!(" [{}, {:?}]", geometry, mapping.function);
+        if let Some(seed) = self.seed {
+            description += &format!(" seed {}", seed);
+        }
+        description
+    }
+}
+
+/// Expand the spec into its jobs. Every mode is run once for each distinct cache it builds from
+/// the geometries, and seeds are only combined with the modes that draw random numbers, so no
+/// run is repeated.
+fn jobs(spec: &ExperimentSpec) -> Result<Vec<Job<'_>>, String> {
+    let mut jobs = Vec::new();
+    for (i, benchmark) in spec.benchmarks.iter().enumerate() {
+        if spec.benchmarks[..i]
+            .iter()
+            .any(|b| b.name == benchmark.name)
+        {
+            return Err(format!("benchmark {} is listed twice", benchmark.name));
+        }
+        for &mode in &spec.modes {
+            if mode.uses_leases() && benchmark.lease_table.is_none() {
+                return Err(format!(
+                    "benchmark {} has no lease_table, which {} mode needs",
+                    benchmark.name,
+                    mode.name()
+                ));
+            }
+
+            let mut caches = Vec::new();
+            for (i, geometry) in spec.geometries.iter().enumerate() {
+                let cache = geometry
+                    .resolve(mode)
+                    .map_err(|e| format!("geometry {}: {}", i + 1, e))?;
+                // geometries differing only in options the mode ignores give the same cache
+                if !caches.contains(&cache) {
+                    caches.push(cache);
+                }
+            }
+            let seeds: Vec<Option<u64>> = if mode.uses_seed() {
+                spec.seeds.iter().copied().map(Some).collect()
+            } else {
+                vec![None]
+            };
+
+            for &cache in &caches {
+                for &seed in &seeds {
+                    jobs.push(Job {
+                        benchmark,
+                        mode,
+                        cache,
+                        seed,
+                    });
+                }
+            }
+        }
+    }
+    Ok(jobs)
+}
+
+/// Run every job of the experiment in parallel and write one row per job to the results
+/// table, in the order of the spec. Jobs that fail are reported and left out of the table.
+pub fn run_batch(spec: &ExperimentSpec, output: &str) -> Result<(), String> {
+    let jobs = jobs(spec)?;
+
+    let mut tables = HashMap::new();
+    for benchmark in &spec.benchmarks {
+        if let Some(path) = &benchmark.lease_table {
+            let table = LeaseTable::load(path).map_err(|e| format!("{}: {}", path, e))?;
+            tables.insert(benchmark.name.as_str(), table);
+        }
+    }
+
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = spec.threads {
+        pool = pool.num_threads(threads);
+    }
+    let pool = pool.build().map_err(|e| e.to_string())?;
+
+    println!("Running {} simulations", jobs.len());
+    let results: Vec<io::Result<SimResult>> = pool.install(|| {
+        jobs.par_iter()
+            .map(|job| {
+                let table = tables.get(job.benchmark.name.as_str());
+                let result = job.run(table);
+                match &result {
+                    Ok(result) => {
+                        println!("{}: miss ratio {}", job.describe(), result.miss_ratio)
+                    }
+                    Err(e) => eprintln!("{}: {}", job.describe(), e),
+                }
+                result
+            })
+            .collect()
+    });
+
+    let mut failed = 0;
+    let mut rows = Vec::new();
+    for (job, result) in jobs.iter().zip(results) {
+        let Ok(result) = result else {
+            failed += 1;
+            continue;
+        };
+        let row = ResultRow {
+            benchmark: Some(&job.benchmark.name),
+            mode: job.mode.name(),
+            trace: &job.benchmark.trace,
+            lease_table: job
+                .mode
+                .uses_leases()
+                .then_some(job.benchmark.lease_table.as_deref())
+                .flatten(),
+            geometry: Some(job.cache.0),
+            index: Some(job.cache.1.function),
+            seed: job.seed,
+            result,
+        };
+        rows.push(row.fields());
+    }
+
+    write_rows(output, &ResultRow::HEADER, &rows).map_err(|e| e.to_string())?;
+    println!("Wrote {} results to {}", rows.len(), output);
+
+    if failed > 0 {
+        return Err(format!("{} of {} simulations failed", failed, jobs.len()));
+    }
+    Ok(())
+}
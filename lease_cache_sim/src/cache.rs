@@ -2,10 +2,16 @@ use std::io;
 use std::io::Write;
 
 use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::CacheGeometry;
+use crate::index::AddressMapping;
+use crate::lease_table::SimRng;
+use crate::snapshot::Snapshot;
 
 // use crate::{LeaseTable, pack_to_cache_block, Trace};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[allow(unused)]
 pub struct CacheBlock {
     _size: u64,
@@ -15,6 +21,9 @@ pub struct CacheBlock {
     pub block_offset: u64,
     pub remaining_lease: u64,
     pub tenancy: u64,
+    /// whether the block was written since it was brought in
+    #[serde(default)]
+    pub dirty: bool,
 }
 
 impl CacheBlock {
@@ -27,6 +36,7 @@ impl CacheBlock {
             block_offset: 0,
             remaining_lease: 0,
             tenancy: 0,
+            dirty: false,
         }
     }
 
@@ -44,29 +54,82 @@ impl CacheBlock {
     }
 }
 
-struct CacheSet {
+/// The outcome of a single access to the cache.
+#[derive(Debug, Clone, Copy)]
+pub struct AccessResult {
+    pub hit: bool,
+    pub forced_evictions: u64,
+}
+
+/// Dirty blocks written back to memory, by why they left the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Writebacks {
+    /// blocks whose lease ran out
+    pub expiry: u64,
+    /// blocks evicted to make room before their lease ran out
+    pub eviction: u64,
+}
+
+impl Writebacks {
+    pub fn add(&mut self, other: &Writebacks) {
+        self.expiry += other.expiry;
+        self.eviction += other.eviction;
+    }
+}
+
+/// The interface shared by the physical and the virtual lease cache.
+pub trait LeaseCache {
+    /// simulate one access, advancing the cache by one step
+    fn update(&mut self, block: CacheBlock, rng: &mut SimRng) -> AccessResult;
+
+    /// the number of accesses simulated so far
+    fn step(&self) -> u64;
+
+    fn calculate_miss_ratio(&self) -> f64;
+
+    fn forced_evictions(&self) -> u64;
+
+    fn writebacks(&self) -> Writebacks;
+
+    fn snapshot(&self) -> Snapshot;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CacheSet {
     block_num: u64,
     blocks: Vec<CacheBlock>,
     forced_eviction: u64,
     miss: i32,
+    #[serde(default)]
+    writebacks: Writebacks,
 }
 
 impl CacheSet {
-    fn new(size: u64) -> CacheSet {
+    pub fn new(size: u64) -> CacheSet {
         CacheSet {
             block_num: size,
             blocks: Vec::new(),
             forced_eviction: 0,
             miss: 0,
+            writebacks: Writebacks::default(),
         }
     }
 
+    pub fn writebacks(&self) -> Writebacks {
+        self.writebacks
+    }
+
+    fn contains(&self, tag: u64) -> bool {
+        self.blocks.iter().any(|block| block.tag == tag)
+    }
+
     /// push a cache block to the cache set. If the cache set is full, evict a cache block randomly. If the cache block is already in the cache, refresh it. Otherwise, push it to the cache set.
-    fn push_to_set(&mut self, new_block: CacheBlock) {
+    fn push_to_set(&mut self, new_block: CacheBlock, rng: &mut SimRng) {
         //if cacheBlock is in the cache, refresh it
         for block in &mut self.blocks {
             if block.tag == new_block.tag {
                 block.remaining_lease = new_block.remaining_lease;
+                block.dirty |= new_block.dirty;
                 return;
             }
         }
@@ -75,63 +138,85 @@ impl CacheSet {
 
         // if cache is full, evict ----------------------------------------
         if self.blocks.len() == self.block_num as usize {
-            self.random_evict();
+            self.random_evict(rng);
         }
         self.blocks.push(new_block);
     }
 
-    fn random_evict(&mut self) -> CacheBlock {
-        let mut rng = rand::thread_rng();
+    /// Simulate an access to this set after it has been aged to the current step.
+    pub fn access(&mut self, block: CacheBlock, rng: &mut SimRng) -> AccessResult {
+        self.push_to_set(block, rng);
+        let result = AccessResult {
+            hit: self.miss == 0,
+            forced_evictions: self.forced_eviction,
+        };
+        self.forced_eviction = 0;
+        self.miss = 0;
+        result
+    }
+
+    fn random_evict(&mut self, rng: &mut SimRng) -> CacheBlock {
         let index = rng.gen_range(0..self.blocks.len());
         self.forced_eviction += 1;
-        self.blocks.remove(index)
+        let evicted = self.blocks.remove(index);
+        self.writebacks.eviction += evicted.dirty as u64;
+        evicted
     }
 
     /// update the remaining lease of each cache block in the cache set
     fn update(&mut self) {
-        self.blocks.retain(|block| block.remaining_lease > 1);
+        self.age(1);
+    }
+
+    /// Count the leases down by `steps` accesses at once, dropping the blocks that expire on the
+    /// way. The same as calling `update` `steps` times.
+    pub fn age(&mut self, steps: u64) {
+        let writebacks = &mut self.writebacks;
+        self.blocks.retain(|block| {
+            let expired = block.remaining_lease <= steps;
+            writebacks.expiry += (expired && block.dirty) as u64;
+            !expired
+        });
         self.blocks.iter_mut().for_each(|block| {
-            block.tenancy += 1;
-            block.remaining_lease -= 1;
+            block.tenancy += steps;
+            block.remaining_lease -= steps;
         });
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Cache {
-    _size: u64,
+    geometry: CacheGeometry,
     sets: Vec<CacheSet>,
-    pub(crate) step: u64,
-    pub(crate) forced_eviction_counter: u64,
+    /// set when the cache is skewed-associative, in which case each way is a bank of
+    /// single-block sets: way `w` of set `s` is `sets[w * num_sets + s]`
+    skewed: Option<AddressMapping>,
+    step: u64,
+    forced_eviction_counter: u64,
     miss_counter: u64,
 }
 
 impl Cache {
-    pub fn new(size: u64, associativity: u64) -> Cache {
-        let sets: Vec<CacheSet> = (0..associativity)
-            .map(|_| CacheSet::new(size / associativity))
-            .collect();
+    pub fn new(geometry: &CacheGeometry, mapping: &AddressMapping) -> Cache {
+        let skewed = mapping.is_skewed().then_some(*mapping);
+        let sets: Vec<CacheSet> = match skewed {
+            Some(_) => (0..geometry.capacity_blocks())
+                .map(|_| CacheSet::new(1))
+                .collect(),
+            None => (0..geometry.sets)
+                .map(|_| CacheSet::new(geometry.ways))
+                .collect(),
+        };
         Cache {
-            _size: size,
+            geometry: *geometry,
             sets,
+            skewed,
             step: 0,
             forced_eviction_counter: 0,
             miss_counter: 0,
         }
     }
 
-    /// update the cache status
-    pub fn update(&mut self, block: CacheBlock) {
-        // update all cache blocks in all the sets
-        self.sets.iter_mut().for_each(|set| set.update());
-        let set_index = block.set_index as usize;
-        self.sets[set_index].push_to_set(block);
-        self.step += 1;
-        self.forced_eviction_counter += self.sets[set_index].forced_eviction; //double counting
-        self.miss_counter += self.sets[set_index].miss as u64;
-        self.sets[set_index].forced_eviction = 0;
-        self.sets[set_index].miss = 0;
-    }
-
     #[allow(unused)]
     pub fn print(&self, output_file: &str) -> io::Result<()> {
         let mut file = std::fs::OpenOptions::new()
@@ -168,7 +253,79 @@ impl Cache {
         Ok(())
     }
 
-    pub(crate) fn calculate_miss_ratio(&self) -> f64 {
+    /// Choose the bank a block goes to in a skewed-associative cache: the one already holding
+    /// it, otherwise an empty one, otherwise a random one.
+    fn skewed_set(&self, mapping: &AddressMapping, block: &CacheBlock, rng: &mut SimRng) -> usize {
+        let num_sets = mapping.num_sets();
+        let candidates: Vec<usize> = (0..self.geometry.ways)
+            .map(|way| (way * num_sets + mapping.set_index(block.address, way)) as usize)
+            .collect();
+
+        candidates
+            .iter()
+            .find(|&&index| self.sets[index].contains(block.tag))
+            .or_else(|| {
+                candidates
+                    .iter()
+                    .find(|&&index| self.sets[index].blocks.is_empty())
+            })
+            .copied()
+            .unwrap_or_else(|| candidates[rng.gen_range(0..candidates.len())])
+    }
+}
+
+impl LeaseCache for Cache {
+    /// update the cache status
+    fn update(&mut self, block: CacheBlock, rng: &mut SimRng) -> AccessResult {
+        // update all cache blocks in all the sets
+        self.sets.iter_mut().for_each(|set| set.update());
+        let set_index = match self.skewed {
+            Some(mapping) => self.skewed_set(&mapping, &block, rng),
+            None => block.set_index as usize,
+        };
+        let result = self.sets[set_index].access(block, rng);
+        self.step += 1;
+        self.forced_eviction_counter += result.forced_evictions;
+        self.miss_counter += !result.hit as u64;
+        result
+    }
+
+    fn step(&self) -> u64 {
+        self.step
+    }
+
+    fn calculate_miss_ratio(&self) -> f64 {
         self.miss_counter as f64 / self.step as f64
     }
+
+    fn forced_evictions(&self) -> u64 {
+        self.forced_eviction_counter
+    }
+
+    fn writebacks(&self) -> Writebacks {
+        let mut writebacks = Writebacks::default();
+        for set in &self.sets {
+            writebacks.add(&set.writebacks);
+        }
+        writebacks
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            step: self.step,
+            occupancy: match self.skewed {
+                Some(mapping) => {
+                    let num_sets = mapping.num_sets() as usize;
+                    let mut occupancy = vec![0; num_sets];
+                    for (index, set) in self.sets.iter().enumerate() {
+                        occupancy[index % num_sets] += set.blocks.len();
+                    }
+                    occupancy
+                }
+                None => self.sets.iter().map(|set| set.blocks.len()).collect(),
+            },
+            miss_ratio: self.calculate_miss_ratio(),
+            forced_evictions: self.forced_eviction_counter,
+        }
+    }
 }
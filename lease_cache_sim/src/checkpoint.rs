@@ -0,0 +1,342 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::CacheGeometry;
+use crate::index::AddressMapping;
+use crate::lease_table::SimRng;
+use crate::report::RefReport;
+
+/// The settings a simulation was started with, which it must be resumed with.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunSettings {
+    pub mode: String,
+    pub trace: String,
+    pub geometry: CacheGeometry,
+    pub mapping: AddressMapping,
+    /// the seed given on the command line, if any
+    pub seed: Option<u64>,
+}
+
+/// The number of bytes of every output a run had written when a checkpoint was taken, if it
+/// writes that output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct OutputLengths {
+    pub event_log: Option<u64>,
+    pub snapshots: Option<u64>,
+}
+
+/// Everything needed to continue a simulation from the middle of a trace.
+///
+/// `position` is the number of trace records already simulated; the cache carries its own
+/// counters, remaining leases and tenancies. The per-reference counters are kept if the run
+/// writes a `--ref-report`, and the lengths of the outputs so that a resumed run drops what was
+/// written after the checkpoint.
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint<C> {
+    pub settings: RunSettings,
+    pub position: u64,
+    pub rng: SimRng,
+    #[serde(default)]
+    pub ref_report: Option<RefReport>,
+    #[serde(default)]
+    pub outputs: OutputLengths,
+    pub cache: C,
+}
+
+impl<C: DeserializeOwned> Checkpoint<C> {
+    /// Load a checkpoint, checking that it was taken with the settings it is resumed with before
+    /// the cache itself is decoded. A resumed run without a seed continues with the generator
+    /// state of the checkpoint, whatever seed it was started with.
+    pub fn load(file_path: &str, settings: &RunSettings) -> io::Result<Checkpoint<C>> {
+        let file = File::open(file_path)?;
+        let checkpoint: Checkpoint<serde_json::Value> =
+            serde_json::from_reader(BufReader::new(file))?;
+
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+        let taken = &checkpoint.settings;
+        if taken.mode != settings.mode {
+            return Err(invalid(format!(
+                "checkpoint was taken in {} mode, but {} mode was requested",
+                taken.mode, settings.mode
+            )));
+        }
+        if taken.trace != settings.trace {
+            return Err(invalid(format!(
+                "checkpoint was taken on trace {}, but trace {} was given",
+                taken.trace, settings.trace
+            )));
+        }
+        if taken.geometry != settings.geometry {
+            return Err(invalid(format!(
+                "checkpoint was taken with a cache of {}, but a cache of {} was given",
+                taken.geometry, settings.geometry
+            )));
+        }
+        if taken.mapping.function != settings.mapping.function {
+            return Err(invalid(format!(
+                "checkpoint was taken with the {:?} set index, but the {:?} set index was given",
+                taken.mapping.function, settings.mapping.function
+            )));
+        }
+        if taken.mapping != settings.mapping {
+            return Err(invalid(
+                "checkpoint was taken with a different set index mask".to_string(),
+            ));
+        }
+        if let Some(seed) = settings.seed.filter(|&seed| taken.seed != Some(seed)) {
+            let taken_seed = taken
+                .seed
+                .map_or("none".to_string(), |seed| seed.to_string());
+            return Err(invalid(format!(
+                "checkpoint was taken with seed {}, but seed {} was given",
+                taken_seed, seed
+            )));
+        }
+
+        Ok(Checkpoint {
+            settings: checkpoint.settings,
+            position: checkpoint.position,
+            rng: checkpoint.rng,
+            ref_report: checkpoint.ref_report,
+            outputs: checkpoint.outputs,
+            cache: serde_json::from_value(checkpoint.cache)?,
+        })
+    }
+}
+
+/// Saves a checkpoint every `interval` accesses, replacing the previous one.
+pub struct Checkpointer {
+    file_path: String,
+    interval: u64,
+    settings: RunSettings,
+}
+
+impl Checkpointer {
+    pub fn new(file_path: &str, interval: u64, settings: RunSettings) -> Checkpointer {
+        Checkpointer {
+            file_path: file_path.to_string(),
+            interval,
+            settings,
+        }
+    }
+
+    /// whether a checkpoint is due after `position` accesses
+    pub fn is_due(&self, position: u64) -> bool {
+        position.is_multiple_of(self.interval)
+    }
+
+    /// Write the checkpoint to a temporary file first so a crash while saving never leaves a
+    /// truncated checkpoint behind.
+    pub fn save<C: Serialize>(
+        &self,
+        cache: &C,
+        rng: &SimRng,
+        position: u64,
+        ref_report: Option<&RefReport>,
+        outputs: OutputLengths,
+    ) -> io::Result<()> {
+        let checkpoint = Checkpoint {
+            settings: self.settings.clone(),
+            position,
+            rng: rng.clone(),
+            ref_report: ref_report.cloned(),
+            outputs,
+            cache,
+        };
+
+        let tmp_path = format!("{}.tmp", self.file_path);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &checkpoint)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(tmp_path, &self.file_path)
+    }
+}
+
+/// Reopen an output of a resumed simulation for appending, cut back to the `len` bytes it had
+/// when the checkpoint was taken.
+pub fn reopen_at_checkpoint(file_path: &str, len: u64) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?;
+    if file.metadata()?.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is shorter than the {} bytes it had at the checkpoint",
+                file_path, len
+            ),
+        ));
+    }
+    file.set_len(len)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{Cache, LeaseCache};
+    use crate::event_log::EventLog;
+    use crate::fully_associative::FullyAssociativeCache;
+    use crate::geometry::Capacity;
+    use crate::index::IndexFunction;
+    use crate::lease_table::{run_trace, test_trace, RunOutputs, Trace};
+    use crate::virtual_cache::VirtualCache;
+    use rand::SeedableRng;
+
+    fn settings(trace: &str, sets: u64, seed: Option<u64>) -> RunSettings {
+        let ways = 64 / sets;
+        let geometry =
+            CacheGeometry::from_params(Some(Capacity::Blocks(64)), Some(ways), 3, None).unwrap();
+        let mapping = AddressMapping::new(IndexFunction::Modulo, &geometry, None).unwrap();
+        RunSettings {
+            mode: "physical".to_string(),
+            trace: trace.to_string(),
+            geometry,
+            mapping,
+            seed,
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let file = format!("lease_cache_sim_{}_{}", std::process::id(), name);
+        std::env::temp_dir()
+            .join(file)
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Simulate 3000 accesses at once, and 1500 with a checkpoint after the first 1000 then the
+    /// rest from that checkpoint, with the same results, per-reference counts and event log.
+    fn check_resume<C: LeaseCache + Serialize + DeserializeOwned>(
+        name: &str,
+        sets: u64,
+        fresh: impl Fn(&RunSettings) -> C,
+    ) {
+        let (trace_path, table) = test_trace(&format!("{}_full", name), 3000, 1);
+        let (prefix_path, _) = test_trace(&format!("{}_prefix", name), 1500, 1);
+        let settings = settings(&trace_path, sets, Some(7));
+        let mapping = settings.mapping;
+        let rng = SimRng::seed_from_u64(7);
+        let run = |cache: C, trace: Trace, rng: &SimRng, outputs: &mut RunOutputs| {
+            run_trace(cache, trace, &table, &mapping, rng, outputs)
+        };
+
+        let uninterrupted_log = temp_path(&format!("{}_uninterrupted_log", name));
+        let mut uninterrupted_outputs = RunOutputs {
+            event_log: Some(EventLog::create(&uninterrupted_log).unwrap()),
+            ref_report: Some(RefReport::default()),
+            ..RunOutputs::default()
+        };
+        let trace = Trace::new(&trace_path).unwrap();
+        let uninterrupted = run(fresh(&settings), trace, &rng, &mut uninterrupted_outputs);
+
+        let checkpoint_path = temp_path(&format!("{}_checkpoint", name));
+        let log = temp_path(&format!("{}_log", name));
+        let mut outputs = RunOutputs {
+            event_log: Some(EventLog::create(&log).unwrap()),
+            checkpoints: Some(Checkpointer::new(&checkpoint_path, 1000, settings.clone())),
+            ref_report: Some(RefReport::default()),
+            ..RunOutputs::default()
+        };
+        run(
+            fresh(&settings),
+            Trace::new(&prefix_path).unwrap(),
+            &rng,
+            &mut outputs,
+        );
+
+        let checkpoint: Checkpoint<C> = Checkpoint::load(&checkpoint_path, &settings).unwrap();
+        assert_eq!(checkpoint.position, 1000);
+        let mut trace = Trace::new(&trace_path).unwrap();
+        trace.advance(checkpoint.position);
+        let log_len = checkpoint.outputs.event_log.unwrap();
+        let mut outputs = RunOutputs {
+            event_log: Some(EventLog::resume(&log, log_len).unwrap()),
+            ref_report: checkpoint.ref_report,
+            ..RunOutputs::default()
+        };
+        let resumed = run(checkpoint.cache, trace, &checkpoint.rng, &mut outputs);
+        let resumed_events = fs::read(&log).unwrap();
+        let uninterrupted_events = fs::read(&uninterrupted_log).unwrap();
+        for path in [
+            &trace_path,
+            &prefix_path,
+            &checkpoint_path,
+            &log,
+            &uninterrupted_log,
+        ] {
+            fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(resumed, uninterrupted);
+        assert_eq!(outputs.ref_report, uninterrupted_outputs.ref_report);
+        assert_eq!(resumed_events, uninterrupted_events);
+    }
+
+    #[test]
+    fn resumed_physical_run_matches_uninterrupted_run() {
+        check_resume("resume_physical", 16, |settings| {
+            Cache::new(&settings.geometry, &settings.mapping)
+        });
+    }
+
+    #[test]
+    fn resumed_fully_associative_run_matches_uninterrupted_run() {
+        check_resume("resume_fully_associative", 1, |settings| {
+            FullyAssociativeCache::new(Some(settings.geometry.capacity_blocks()))
+        });
+    }
+
+    #[test]
+    fn resumed_virtual_run_matches_uninterrupted_run() {
+        check_resume("resume_virtual", 16, |settings| {
+            VirtualCache::new(&settings.geometry)
+        });
+    }
+
+    #[test]
+    fn rejects_resuming_with_other_settings() {
+        let path = temp_path("resume_settings");
+        let taken = settings("trace.txt", 16, Some(7));
+        Checkpointer::new(&path, 1, taken.clone())
+            .save(
+                &0u64,
+                &SimRng::seed_from_u64(7),
+                1,
+                None,
+                OutputLengths::default(),
+            )
+            .unwrap();
+
+        let load = |settings: &RunSettings| Checkpoint::<u64>::load(&path, settings);
+        assert!(load(&taken).is_ok());
+        // the generator state is in the checkpoint, so the seed may be left out
+        assert!(load(&RunSettings {
+            seed: None,
+            ..taken.clone()
+        })
+        .is_ok());
+        assert!(load(&RunSettings {
+            seed: Some(8),
+            ..taken.clone()
+        })
+        .is_err());
+        assert!(load(&settings("trace.txt", 8, Some(7))).is_err());
+        assert!(load(&settings("other.txt", 16, Some(7))).is_err());
+        assert!(load(&RunSettings {
+            mode: "virtual".to_string(),
+            ..taken.clone()
+        })
+        .is_err());
+        let mut xor = taken.clone();
+        xor.mapping.function = IndexFunction::XorFold;
+        assert!(load(&xor).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
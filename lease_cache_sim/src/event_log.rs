@@ -0,0 +1,333 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use crate::checkpoint::reopen_at_checkpoint;
+use crate::ref_labels::RefLabels;
+
+/// One simulated access, as recorded in an event log.
+///
+/// Each event is written as a single line
+/// `step,address,reference,set,H|M,lease,evictions`, with address, reference and lease in hex
+/// (matching the trace and lease table files) and the rest in decimal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub step: u64,
+    pub address: u64,
+    pub reference: u64,
+    pub set_index: u64,
+    pub hit: bool,
+    pub lease: u64,
+    pub evictions: u64,
+}
+
+impl Event {
+    pub fn print(&self) -> String {
+        format!(
+            "{},{:x},{:x},{},{},{:x},{}",
+            self.step,
+            self.address,
+            self.reference,
+            self.set_index,
+            if self.hit { "H" } else { "M" },
+            self.lease,
+            self.evictions
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<Event> {
+        let parts: Vec<&str> = line.trim().split(',').collect();
+        if parts.len() != 7 {
+            return None;
+        }
+
+        let hit = match parts[4] {
+            "H" => true,
+            "M" => false,
+            _ => return None,
+        };
+
+        Some(Event {
+            step: parts[0].parse().ok()?,
+            address: u64::from_str_radix(parts[1], 16).ok()?,
+            reference: u64::from_str_radix(parts[2], 16).ok()?,
+            set_index: parts[3].parse().ok()?,
+            hit,
+            lease: u64::from_str_radix(parts[5], 16).ok()?,
+            evictions: parts[6].parse().ok()?,
+        })
+    }
+}
+
+/// A buffered writer for the per-access event stream.
+pub struct EventLog {
+    writer: BufWriter<File>,
+}
+
+impl EventLog {
+    pub fn create(file_path: &str) -> io::Result<EventLog> {
+        let file = File::create(file_path)?;
+        Ok(EventLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Continue the event log of a simulation resumed from a checkpoint taken when it was `len`
+    /// bytes long, keeping the events before the checkpoint and dropping any written after it.
+    pub fn resume(file_path: &str, len: u64) -> io::Result<EventLog> {
+        let file = reopen_at_checkpoint(file_path, len)?;
+        Ok(EventLog {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn record(&mut self, event: &Event) -> io::Result<()> {
+        writeln!(self.writer, "{}", event.print())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// the length of the file, counting only the events flushed to it
+    pub fn flushed_len(&self) -> io::Result<u64> {
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+}
+
+/// Reads an event log back, skipping lines that do not parse as events.
+pub struct EventLogReader {
+    lines: io::Lines<BufReader<File>>,
+}
+
+impl EventLogReader {
+    pub fn new(file_path: &str) -> io::Result<Self> {
+        let file = File::open(file_path)?;
+        Ok(EventLogReader {
+            lines: BufReader::new(file).lines(),
+        })
+    }
+}
+
+impl Iterator for EventLogReader {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            let line = line.ok()?;
+            if let Some(event) = Event::parse(&line) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+/// Agreement between two runs for a single reference.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReferenceDiff {
+    pub compared: u64,
+    /// hit in the left log, miss in the right log
+    pub hit_miss: u64,
+    /// miss in the left log, hit in the right log
+    pub miss_hit: u64,
+}
+
+impl ReferenceDiff {
+    pub fn disagreements(&self) -> u64 {
+        self.hit_miss + self.miss_hit
+    }
+}
+
+pub struct EventDiff {
+    pub compared: u64,
+    pub left_only: u64,
+    pub right_only: u64,
+    pub first_divergence: Option<(Event, Event)>,
+    pub address_mismatches: u64,
+    pub references: BTreeMap<u64, ReferenceDiff>,
+}
+
+/// Align two event logs by step and compare the outcome of every access present in both.
+///
+/// Steps missing from one side are counted but not compared, so a log that only covers part of
+/// the run (e.g. a hardware sample) can still be checked against a full simulation.
+pub fn diff_event_logs(left: EventLogReader, right: EventLogReader) -> EventDiff {
+    let mut diff = EventDiff {
+        compared: 0,
+        left_only: 0,
+        right_only: 0,
+        first_divergence: None,
+        address_mismatches: 0,
+        references: BTreeMap::new(),
+    };
+
+    let mut left = left.peekable();
+    let mut right = right.peekable();
+
+    loop {
+        let (l, r) = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => (*l, *r),
+            (Some(_), None) => {
+                diff.left_only += left.count() as u64;
+                break;
+            }
+            (None, Some(_)) => {
+                diff.right_only += right.count() as u64;
+                break;
+            }
+            (None, None) => break,
+        };
+
+        if l.step < r.step {
+            diff.left_only += 1;
+            left.next();
+            continue;
+        }
+        if r.step < l.step {
+            diff.right_only += 1;
+            right.next();
+            continue;
+        }
+        left.next();
+        right.next();
+
+        diff.compared += 1;
+        let diverged = if l.address != r.address {
+            diff.address_mismatches += 1;
+            true
+        } else {
+            let entry = diff.references.entry(l.reference).or_default();
+            entry.compared += 1;
+            match (l.hit, r.hit) {
+                (true, false) => entry.hit_miss += 1,
+                (false, true) => entry.miss_hit += 1,
+                _ => {}
+            }
+            l.hit != r.hit
+        };
+
+        if diverged && diff.first_divergence.is_none() {
+            diff.first_divergence = Some((l, r));
+        }
+    }
+
+    diff
+}
+
+impl EventDiff {
+    /// Print the report, naming the references with `labels` if given.
+    pub fn print_report(&self, labels: Option<&RefLabels>) {
+        println!(
+            "Compared accesses: {} (left only: {}, right only: {})",
+            self.compared, self.left_only, self.right_only
+        );
+        if self.address_mismatches > 0 {
+            println!(
+                "Address mismatches: {} (the logs were not produced from the same trace)",
+                self.address_mismatches
+            );
+        }
+
+        match &self.first_divergence {
+            Some((l, r)) => {
+                println!("First divergence at step {}:", l.step);
+                println!("  left:  {}", l.print());
+                println!("  right: {}", r.print());
+            }
+            None => {
+                println!("No divergence found");
+                return;
+            }
+        }
+
+        let mut references: Vec<_> = self
+            .references
+            .iter()
+            .filter(|(_, r)| r.disagreements() > 0)
+            .collect();
+        references.sort_by_key(|(_, r)| Reverse(r.disagreements()));
+
+        match labels {
+            Some(_) => println!("reference, label, compared, hit->miss, miss->hit, disagreement"),
+            None => println!("reference, compared, hit->miss, miss->hit, disagreement"),
+        }
+        for (reference, r) in references {
+            let label = labels.map_or(String::new(), |labels| {
+                format!(", {}", labels.label(*reference))
+            });
+            println!(
+                "{:x}{}, {}, {}, {}, {}",
+                reference,
+                label,
+                r.compared,
+                r.hit_miss,
+                r.miss_hit,
+                r.disagreements() as f64 / r.compared as f64
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_log(name: &str, events: &[Event]) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("lease_cache_sim_{}_{}", std::process::id(), name))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut log = EventLog::create(&path).unwrap();
+        for event in events {
+            log.record(event).unwrap();
+        }
+        log.flush().unwrap();
+        path
+    }
+
+    fn event(step: u64, reference: u64, hit: bool) -> Event {
+        Event {
+            step,
+            address: 0x100 + 8 * step,
+            reference,
+            set_index: 0,
+            hit,
+            lease: 4,
+            evictions: 0,
+        }
+    }
+
+    #[test]
+    fn finds_the_first_divergence() {
+        // the right log skips step 1 and goes on to step 6, and the logs disagree at steps 3
+        // and 4
+        let mut left: Vec<Event> = (0..6).map(|step| event(step, step % 2, true)).collect();
+        left[4].hit = false;
+        let mut right: Vec<Event> = (0..7).map(|step| event(step, step % 2, true)).collect();
+        right[3].hit = false;
+        right.remove(1);
+        let left_path = write_log("diff_left", &left);
+        let right_path = write_log("diff_right", &right);
+
+        let diff = diff_event_logs(
+            EventLogReader::new(&left_path).unwrap(),
+            EventLogReader::new(&right_path).unwrap(),
+        );
+        std::fs::remove_file(&left_path).unwrap();
+        std::fs::remove_file(&right_path).unwrap();
+
+        assert_eq!(diff.compared, 5);
+        assert_eq!(diff.left_only, 1);
+        assert_eq!(diff.right_only, 1);
+        assert_eq!(diff.address_mismatches, 0);
+        let (l, r) = diff.first_divergence.unwrap();
+        assert_eq!((l.step, l.hit, r.hit), (3, true, false));
+        let odd = diff.references[&1];
+        assert_eq!((odd.compared, odd.hit_miss, odd.miss_hit), (2, 1, 0));
+        let even = diff.references[&0];
+        assert_eq!((even.compared, even.hit_miss, even.miss_hit), (3, 0, 1));
+    }
+}
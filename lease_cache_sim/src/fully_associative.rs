@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{AccessResult, CacheBlock, LeaseCache, Writebacks};
+use crate::lease_table::SimRng;
+use crate::snapshot::Snapshot;
+
+#[derive(Serialize, Deserialize)]
+struct ResidentBlock {
+    block: CacheBlock,
+    /// the step at whose start the lease has run out
+    expiry: u64,
+}
+
+/// A fully-associative lease cache with constant-time accesses.
+///
+/// Blocks are found through a hash map from tag to slot instead of scanning the set, and leases
+/// are not counted down on every access: each block records the step its lease runs out at and
+/// is filed in a bucket for that step, which is emptied when the step is reached. The hits,
+/// misses and expirations are the same as in a single-set `Cache`. A forced eviction still
+/// picks a uniformly random block, but slots are reordered on removal, so for a given seed it
+/// is not necessarily the same block `Cache` would pick.
+///
+/// Without a capacity the cache never evicts, which makes it a fully-associative virtual cache.
+#[derive(Serialize, Deserialize)]
+pub struct FullyAssociativeCache {
+    capacity: Option<usize>,
+    blocks: Vec<ResidentBlock>,
+    slots: HashMap<u64, usize>,
+    expiring: HashMap<u64, Vec<u64>>,
+    step: u64,
+    forced_eviction_counter: u64,
+    miss_counter: u64,
+    #[serde(default)]
+    writebacks: Writebacks,
+}
+
+impl FullyAssociativeCache {
+    pub fn new(capacity: Option<u64>) -> FullyAssociativeCache {
+        FullyAssociativeCache {
+            capacity: capacity.map(|capacity| capacity as usize),
+            blocks: Vec::new(),
+            slots: HashMap::new(),
+            expiring: HashMap::new(),
+            step: 0,
+            forced_eviction_counter: 0,
+            miss_counter: 0,
+            writebacks: Writebacks::default(),
+        }
+    }
+
+    fn remove(&mut self, slot: usize) -> CacheBlock {
+        let removed = self.blocks.swap_remove(slot);
+        self.slots.remove(&removed.block.tag);
+        if let Some(moved) = self.blocks.get(slot) {
+            self.slots.insert(moved.block.tag, slot);
+        }
+        removed.block
+    }
+
+    /// Drop the blocks whose lease ran out at the current step. Entries left behind by blocks
+    /// that were renewed or evicted since are skipped.
+    fn expire(&mut self) {
+        let Some(tags) = self.expiring.remove(&self.step) else {
+            return;
+        };
+        for tag in tags {
+            if let Some(&slot) = self.slots.get(&tag) {
+                if self.blocks[slot].expiry == self.step {
+                    let expired = self.remove(slot);
+                    self.writebacks.expiry += expired.dirty as u64;
+                }
+            }
+        }
+    }
+
+    /// A block with a lease of `lease` accessed now survives the next `lease - 1` accesses,
+    /// matching the count-down in `CacheSet::update`.
+    fn expiry(&self, lease: u64) -> u64 {
+        self.step + lease.max(1)
+    }
+}
+
+impl LeaseCache for FullyAssociativeCache {
+    fn update(&mut self, block: CacheBlock, rng: &mut SimRng) -> AccessResult {
+        self.expire();
+
+        let expiry = self.expiry(block.remaining_lease);
+        let mut result = AccessResult {
+            hit: true,
+            forced_evictions: 0,
+        };
+
+        match self.slots.get(&block.tag) {
+            Some(&slot) => {
+                let resident = &mut self.blocks[slot];
+                resident.block.remaining_lease = block.remaining_lease;
+                resident.block.dirty |= block.dirty;
+                resident.expiry = expiry;
+            }
+            None => {
+                result.hit = false;
+                self.miss_counter += 1;
+                if self.capacity == Some(self.blocks.len()) {
+                    let slot = rng.gen_range(0..self.blocks.len());
+                    let evicted = self.remove(slot);
+                    self.writebacks.eviction += evicted.dirty as u64;
+                    result.forced_evictions = 1;
+                    self.forced_eviction_counter += 1;
+                }
+                self.slots.insert(block.tag, self.blocks.len());
+                self.blocks.push(ResidentBlock { block, expiry });
+            }
+        }
+        self.expiring.entry(expiry).or_default().push(block.tag);
+
+        self.step += 1;
+        result
+    }
+
+    fn step(&self) -> u64 {
+        self.step
+    }
+
+    fn calculate_miss_ratio(&self) -> f64 {
+        self.miss_counter as f64 / self.step as f64
+    }
+
+    fn forced_evictions(&self) -> u64 {
+        self.forced_eviction_counter
+    }
+
+    fn writebacks(&self) -> Writebacks {
+        self.writebacks
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            step: self.step,
+            occupancy: vec![self.blocks.len()],
+            miss_ratio: self.calculate_miss_ratio(),
+            forced_evictions: self.forced_eviction_counter,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+    use crate::geometry::{CacheGeometry, Capacity};
+    use crate::index::{AddressMapping, IndexFunction};
+    use crate::lease_table::{run_trace, run_trace_virtual, test_trace, RunOutputs, Trace};
+    use crate::report::SimResult;
+    use crate::virtual_cache::VirtualCache;
+    use rand::SeedableRng;
+
+    /// Simulate the trace with the fully associative engine and with the set-associative one
+    /// configured with a single set.
+    fn compare(name: &str, capacity: Option<u64>) -> (SimResult, SimResult) {
+        let (path, table) = test_trace(name, 5000, 6);
+        let geometry = match capacity {
+            Some(blocks) => {
+                CacheGeometry::from_params(Some(Capacity::Blocks(blocks)), None, 3, Some(0))
+            }
+            None => CacheGeometry::from_params(None, Some(1), 3, Some(0)),
+        }
+        .unwrap();
+        let mapping = AddressMapping::new(IndexFunction::Modulo, &geometry, None).unwrap();
+        let rng = SimRng::seed_from_u64(11);
+        let mut outputs = RunOutputs::default();
+        let trace = || Trace::new(&path).unwrap();
+        let results = match capacity {
+            Some(_) => (
+                run_trace(
+                    FullyAssociativeCache::new(capacity),
+                    trace(),
+                    &table,
+                    &mapping,
+                    &rng,
+                    &mut outputs,
+                ),
+                run_trace(
+                    Cache::new(&geometry, &mapping),
+                    trace(),
+                    &table,
+                    &mapping,
+                    &rng,
+                    &mut outputs,
+                ),
+            ),
+            None => (
+                run_trace_virtual(
+                    FullyAssociativeCache::new(None),
+                    trace(),
+                    &table,
+                    &mapping,
+                    &rng,
+                    &mut outputs,
+                ),
+                run_trace_virtual(
+                    VirtualCache::new(&geometry),
+                    trace(),
+                    &table,
+                    &mapping,
+                    &rng,
+                    &mut outputs,
+                ),
+            ),
+        };
+        std::fs::remove_file(&path).unwrap();
+        results
+    }
+
+    #[test]
+    fn matches_single_set_physical_cache() {
+        // large enough that no block is evicted before its lease ends, where the two engines
+        // may pick different victims
+        let (fully_associative, set_associative) = compare("fully_associative_physical", Some(512));
+        assert_eq!(set_associative.forced_evictions, 0);
+        assert_eq!(fully_associative, set_associative);
+    }
+
+    #[test]
+    fn matches_single_set_virtual_cache() {
+        let (fully_associative, set_associative) = compare("fully_associative_virtual", None);
+        assert_eq!(fully_associative, set_associative);
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// A cache capacity, given either as a number of blocks (`128`) or of bytes (`4096B`, `32KB`,
+/// `1MB`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capacity {
+    Blocks(u64),
+    Bytes(u64),
+}
+
+impl FromStr for Capacity {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let upper = value.to_ascii_uppercase();
+        let (digits, scale) = if let Some(digits) = upper.strip_suffix("KB") {
+            (digits, Some(1 << 10))
+        } else if let Some(digits) = upper.strip_suffix("MB") {
+            (digits, Some(1 << 20))
+        } else if let Some(digits) = upper.strip_suffix('B') {
+            (digits, Some(1))
+        } else {
+            (upper.as_str(), None)
+        };
+
+        let number = digits
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid cache size '{}'", value))?;
+        Ok(match scale {
+            Some(scale) => Capacity::Bytes(
+                number
+                    .checked_mul(scale)
+                    .ok_or_else(|| format!("cache size '{}' is too large", value))?,
+            ),
+            None => Capacity::Blocks(number),
+        })
+    }
+}
+
+impl Capacity {
+    /// The size in blocks of `1 << offset` bytes.
+    pub fn blocks(self, offset: u64) -> Result<u64, String> {
+        let block_size = 1 << offset;
+        match self {
+            Capacity::Blocks(blocks) => Ok(blocks),
+            Capacity::Bytes(bytes) if bytes % block_size != 0 => Err(format!(
+                "cache size of {} bytes is not a multiple of the {}-byte block size",
+                bytes, block_size
+            )),
+            Capacity::Bytes(bytes) => Ok(bytes / block_size),
+        }
+    }
+}
+
+/// The shape of a set-associative cache: `sets` sets of `ways` blocks of `block_size` bytes.
+///
+/// Every mode derives its cache, set index and block offset from the same geometry, so the set
+/// index can never address a set that does not exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheGeometry {
+    pub block_size: u64,
+    pub sets: u64,
+    pub ways: u64,
+}
+
+impl CacheGeometry {
+    /// The number of sets when neither the set count nor the associativity is given.
+    const DEFAULT_SET_BITS: u64 = 7;
+    /// The capacity in blocks when only one of sets and associativity is given.
+    const DEFAULT_CAPACITY: u64 = 128;
+
+    /// Derive a geometry from the command line parameters, any of which may be omitted.
+    ///
+    /// `offset` and `set` are bit widths, as they appear in an address. Two of capacity,
+    /// associativity and set count determine the third; missing ones are filled with
+    /// `1 << DEFAULT_SET_BITS` sets and then `DEFAULT_CAPACITY` blocks until two are known. If
+    /// all three are given they have to agree.
+    pub fn from_params(
+        capacity: Option<Capacity>,
+        associativity: Option<u64>,
+        offset: u64,
+        set: Option<u64>,
+    ) -> Result<CacheGeometry, String> {
+        if offset >= 32 {
+            return Err(format!("block offset of {} bits is too large", offset));
+        }
+        if let Some(set) = set.filter(|&set| set >= 32) {
+            return Err(format!("set index of {} bits is too large", set));
+        }
+        let block_size = 1 << offset;
+
+        let capacity = capacity
+            .map(|capacity| capacity.blocks(offset))
+            .transpose()?;
+        let mut sets = set.map(|set| 1 << set);
+
+        let known = [capacity.is_some(), associativity.is_some(), sets.is_some()]
+            .iter()
+            .filter(|&&known| known)
+            .count();
+        if known < 2 && sets.is_none() {
+            sets = Some(1 << Self::DEFAULT_SET_BITS);
+        }
+        let capacity = match (capacity, associativity, sets) {
+            (None, Some(_), Some(_)) => None,
+            (None, _, _) => Some(Self::DEFAULT_CAPACITY),
+            (capacity, _, _) => capacity,
+        };
+
+        let (ways, sets) = match (capacity, associativity, sets) {
+            (Some(capacity), Some(ways), Some(sets)) => {
+                if ways.checked_mul(sets) != Some(capacity) {
+                    return Err(format!(
+                        "cache size of {} blocks does not match {} sets of {} ways",
+                        capacity, sets, ways
+                    ));
+                }
+                (ways, sets)
+            }
+            (Some(capacity), Some(ways), None) => {
+                if ways == 0 || capacity % ways != 0 || !(capacity / ways).is_power_of_two() {
+                    return Err(format!(
+                        "cache size of {} blocks cannot be split into a power-of-two number of sets of {} ways",
+                        capacity, ways
+                    ));
+                }
+                (ways, capacity / ways)
+            }
+            (Some(capacity), None, Some(sets)) => {
+                if capacity % sets != 0 {
+                    return Err(format!(
+                        "cache size of {} blocks cannot be split evenly into {} sets",
+                        capacity, sets
+                    ));
+                }
+                (capacity / sets, sets)
+            }
+            (None, Some(ways), Some(sets)) => (ways, sets),
+            _ => unreachable!("geometry is underdetermined"),
+        };
+
+        if ways == 0 {
+            return Err("the cache needs at least one way".to_string());
+        }
+        if ways
+            .checked_mul(sets)
+            .and_then(|blocks| blocks.checked_mul(block_size))
+            .is_none()
+        {
+            return Err(format!(
+                "cache of {} sets of {} ways is too large",
+                sets, ways
+            ));
+        }
+
+        Ok(CacheGeometry {
+            block_size,
+            sets,
+            ways,
+        })
+    }
+
+    pub fn capacity_blocks(&self) -> u64 {
+        self.sets * self.ways
+    }
+
+    pub fn capacity_bytes(&self) -> u64 {
+        self.capacity_blocks() * self.block_size
+    }
+
+    /// the width of the block offset in an address
+    pub fn offset_bits(&self) -> u64 {
+        self.block_size.trailing_zeros() as u64
+    }
+
+    /// the width of the set index in an address
+    pub fn set_bits(&self) -> u64 {
+        self.sets.trailing_zeros() as u64
+    }
+}
+
+impl fmt::Display for CacheGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} blocks ({} bytes): {} sets x {} ways, {}-byte blocks",
+            self.capacity_blocks(),
+            self.capacity_bytes(),
+            self.sets,
+            self.ways,
+            self.block_size
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn geometry(
+        capacity: Option<&str>,
+        associativity: Option<u64>,
+        set: Option<u64>,
+    ) -> Result<(u64, u64), String> {
+        let capacity = capacity.map(|capacity| capacity.parse().unwrap());
+        CacheGeometry::from_params(capacity, associativity, 3, set)
+            .map(|geometry| (geometry.sets, geometry.ways))
+    }
+
+    #[test]
+    fn parses_capacities() {
+        assert_eq!("128".parse(), Ok(Capacity::Blocks(128)));
+        assert_eq!("1024B".parse(), Ok(Capacity::Bytes(1024)));
+        assert_eq!(" 32kb".parse(), Ok(Capacity::Bytes(32 << 10)));
+        assert_eq!("1MB".parse(), Ok(Capacity::Bytes(1 << 20)));
+        assert!("32 KiB".parse::<Capacity>().is_err());
+        assert!("99999999999999MB".parse::<Capacity>().is_err());
+        assert_eq!(Capacity::Bytes(1024).blocks(3), Ok(128));
+        assert!(Capacity::Bytes(1020).blocks(3).is_err());
+    }
+
+    #[test]
+    fn fills_in_defaults() {
+        // 128 sets, then a 128-block cache
+        assert_eq!(geometry(None, None, None), Ok((128, 1)));
+        assert_eq!(geometry(Some("512"), None, None), Ok((128, 4)));
+        assert_eq!(geometry(None, Some(4), None), Ok((128, 4)));
+        assert_eq!(geometry(None, None, Some(4)), Ok((16, 8)));
+    }
+
+    #[test]
+    fn derives_the_third_parameter() {
+        assert_eq!(geometry(Some("1KB"), Some(2), None), Ok((64, 2)));
+        assert_eq!(geometry(Some("256"), None, Some(0)), Ok((1, 256)));
+        assert_eq!(geometry(None, Some(8), Some(5)), Ok((32, 8)));
+        assert_eq!(geometry(Some("256"), Some(8), Some(5)), Ok((32, 8)));
+    }
+
+    #[test]
+    fn rejects_inconsistent_parameters() {
+        // all three given but disagreeing
+        assert!(geometry(Some("256"), Some(4), Some(5)).is_err());
+        // sets that are not a power of two
+        assert!(geometry(Some("96"), Some(2), None).is_err());
+        assert!(geometry(Some("128"), Some(3), None).is_err());
+        assert!(geometry(Some("100"), None, Some(3)).is_err());
+        assert!(geometry(Some("128"), Some(0), None).is_err());
+        assert!(geometry(Some("0"), None, Some(2)).is_err());
+        // a byte size that is not a whole number of blocks
+        assert!(geometry(Some("1020B"), None, None).is_err());
+        assert!(CacheGeometry::from_params(None, None, 32, None).is_err());
+        assert!(geometry(None, Some(1), Some(32)).is_err());
+        assert!(geometry(None, Some(u64::MAX), Some(4)).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::geometry::CacheGeometry;
+
+/// How the set index of an access is computed from its block address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum IndexFunction {
+    /// the low set-index bits of the block address
+    Modulo,
+    /// all bits of the block address XOR-folded down to the set-index width
+    XorFold,
+    /// the block address modulo the largest prime not above the number of sets
+    PrimeModulo,
+    /// a different hash per way, as in a skewed-associative cache
+    Skewed,
+    /// the block address bits selected by a user-provided mask
+    Mask,
+}
+
+/// Splits an address into block offset, set index and tag.
+///
+/// The block address is `address >> offset`, and `1 << set` is the number of sets. Every index
+/// function other than `Modulo` keeps the full block address as the tag, since the set index
+/// alone no longer determines which bits were dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressMapping {
+    pub function: IndexFunction,
+    pub offset: u64,
+    pub set: u64,
+    mask: u64,
+    prime: u64,
+}
+
+impl AddressMapping {
+    pub fn new(
+        function: IndexFunction,
+        geometry: &CacheGeometry,
+        mask: Option<u64>,
+    ) -> Result<AddressMapping, String> {
+        let offset = geometry.offset_bits();
+        let set = geometry.set_bits();
+        let mask = match (function, mask) {
+            (IndexFunction::Mask, None) => {
+                return Err("the mask index function needs --index-mask".to_string())
+            }
+            (IndexFunction::Mask, Some(mask)) if mask.count_ones() as u64 != set => {
+                return Err(format!(
+                    "index mask {:#x} selects {} bits, but the set index has {} bits",
+                    mask,
+                    mask.count_ones(),
+                    set
+                ))
+            }
+            (IndexFunction::Mask, Some(mask)) => mask,
+            (_, Some(_)) => {
+                return Err("--index-mask is only used by the mask index function".to_string())
+            }
+            (_, None) => 0,
+        };
+
+        Ok(AddressMapping {
+            function,
+            offset,
+            set,
+            mask,
+            prime: largest_prime_up_to(1 << set),
+        })
+    }
+
+    pub fn num_sets(&self) -> u64 {
+        1 << self.set
+    }
+
+    pub fn is_skewed(&self) -> bool {
+        self.function == IndexFunction::Skewed
+    }
+
+    pub fn block_offset(&self, address: u64) -> u64 {
+        address & ((1 << self.offset) - 1)
+    }
+
+    /// The set an address maps to in the given way. Only the skewed function depends on `way`.
+    pub fn set_index(&self, address: u64, way: u64) -> u64 {
+        let block = address >> self.offset;
+        let set_mask = self.num_sets() - 1;
+        match self.function {
+            IndexFunction::Modulo => block & set_mask,
+            IndexFunction::XorFold => {
+                if self.set == 0 {
+                    return 0;
+                }
+                let mut folded = 0;
+                let mut rest = block;
+                while rest != 0 {
+                    folded ^= rest & set_mask;
+                    rest >>= self.set;
+                }
+                folded
+            }
+            IndexFunction::PrimeModulo => block % self.prime,
+            IndexFunction::Skewed => {
+                (block ^ skew_hash(block >> self.set, way, self.set)) & set_mask
+            }
+            IndexFunction::Mask => extract_bits(block, self.mask),
+        }
+    }
+
+    pub fn tag(&self, address: u64) -> u64 {
+        match self.function {
+            IndexFunction::Modulo => address >> (self.offset + self.set),
+            _ => address >> self.offset,
+        }
+    }
+}
+
+/// A per-way multiplicative hash of the tag bits, `bits` wide.
+fn skew_hash(high: u64, way: u64, bits: u64) -> u64 {
+    if bits == 0 {
+        return 0;
+    }
+    let seed = 0x9e37_79b9_7f4a_7c15_u64.wrapping_add(way.wrapping_mul(0xbf58_476d_1ce4_e5b9));
+    (high ^ way).wrapping_mul(seed | 1) >> (64 - bits)
+}
+
+/// Gather the bits of `value` selected by `mask` into the low bits of the result.
+fn extract_bits(value: u64, mask: u64) -> u64 {
+    let mut result = 0;
+    let mut position = 0;
+    let mut remaining = mask;
+    while remaining != 0 {
+        let bit = remaining.trailing_zeros();
+        result |= ((value >> bit) & 1) << position;
+        position += 1;
+        remaining &= remaining - 1;
+    }
+    result
+}
+
+fn largest_prime_up_to(n: u64) -> u64 {
+    (2..=n)
+        .rev()
+        .find(|&candidate| {
+            (2..)
+                .take_while(|d| d * d <= candidate)
+                .all(|d| candidate % d != 0)
+        })
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Capacity;
+
+    /// A mapping of a direct-mapped cache with 8-byte blocks and 16 sets.
+    fn mapping(function: IndexFunction, mask: Option<u64>) -> AddressMapping {
+        let geometry =
+            CacheGeometry::from_params(Some(Capacity::Blocks(16)), Some(1), 3, None).unwrap();
+        AddressMapping::new(function, &geometry, mask).unwrap()
+    }
+
+    // the block address 0x247, at byte 5 of the block
+    const ADDRESS: u64 = 0x247 << 3 | 5;
+
+    #[test]
+    fn modulo_takes_the_low_block_bits() {
+        let mapping = mapping(IndexFunction::Modulo, None);
+        assert_eq!(mapping.block_offset(ADDRESS), 5);
+        assert_eq!(mapping.set_index(ADDRESS, 0), 0x7);
+        assert_eq!(mapping.tag(ADDRESS), 0x24);
+    }
+
+    #[test]
+    fn xor_fold_folds_every_block_bit() {
+        let mapping = mapping(IndexFunction::XorFold, None);
+        assert_eq!(mapping.set_index(ADDRESS, 0), 0x2 ^ 0x4 ^ 0x7);
+        assert_eq!(mapping.tag(ADDRESS), 0x247);
+    }
+
+    #[test]
+    fn prime_modulo_uses_the_largest_prime_below_the_sets() {
+        let mapping = mapping(IndexFunction::PrimeModulo, None);
+        assert_eq!(mapping.prime, 13);
+        assert_eq!(mapping.set_index(ADDRESS, 0), 0x247 % 13);
+        assert_eq!(mapping.tag(ADDRESS), 0x247);
+        assert_eq!(largest_prime_up_to(128), 127);
+        assert_eq!(largest_prime_up_to(1), 1);
+    }
+
+    #[test]
+    fn mask_gathers_the_selected_bits() {
+        // bits 0, 2, 5 and 7 of 0b10_0100_0111 are 1, 1, 0 and 0
+        let mapping = mapping(IndexFunction::Mask, Some(0b1010_0101));
+        assert_eq!(mapping.set_index(ADDRESS, 0), 0b0011);
+        assert_eq!(mapping.tag(ADDRESS), 0x247);
+    }
+
+    #[test]
+    fn skewed_picks_a_set_per_way() {
+        let mapping = mapping(IndexFunction::Skewed, None);
+        let sets: Vec<u64> = (0..4).map(|way| mapping.set_index(ADDRESS, way)).collect();
+        assert_eq!(sets, [4, 15, 3, 15]);
+        // a block in the same set in the first way is in other sets in the other ways
+        let other = 0x11c << 3;
+        let sets: Vec<u64> = (0..4).map(|way| mapping.set_index(other, way)).collect();
+        assert_eq!(sets, [4, 1, 14, 11]);
+        assert_eq!(mapping.tag(ADDRESS), 0x247);
+    }
+
+    #[test]
+    fn rejects_masks_that_do_not_fit() {
+        let geometry =
+            CacheGeometry::from_params(Some(Capacity::Blocks(16)), Some(1), 3, None).unwrap();
+        assert!(AddressMapping::new(IndexFunction::Mask, &geometry, None).is_err());
+        assert!(AddressMapping::new(IndexFunction::Mask, &geometry, Some(0b111)).is_err());
+        assert!(AddressMapping::new(IndexFunction::Modulo, &geometry, Some(0xf)).is_err());
+    }
+}
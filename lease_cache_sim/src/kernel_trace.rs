@@ -0,0 +1,60 @@
+use crate::lease_table::{AccessType, TraceItem};
+use fxhash::FxHashSet;
+use std::io;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use trace_gen::kernels::find_kernel;
+use trace_gen::ri_utils::DataLayout;
+use trace_gen::tracer::Tracer;
+
+/// The number of accesses the tracer may run ahead of the simulation.
+const BUFFER: usize = 1 << 16;
+
+/// Trace a kernel on a separate thread, from a `NAME:PARAMS` spec such as `mvt:1024` or
+/// `3mm:128,128,128,128,128`. The accesses are sent as they are traced, so the trace is never
+/// held in memory or written to disk.
+pub fn trace_kernel(spec: &str) -> io::Result<Receiver<TraceItem>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+    let kernel = find_kernel(name).map_err(invalid)?;
+    let params = params
+        .split(',')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            param
+                .parse::<usize>()
+                .map_err(|_| invalid(format!("invalid kernel parameter '{}'", param)))
+        })
+        .collect::<io::Result<Vec<usize>>>()?;
+    if params.len() != kernel.params.len() {
+        return Err(invalid(format!(
+            "{} takes the parameters {}",
+            kernel.name,
+            kernel.params.join(", ")
+        )));
+    }
+
+    let (sender, receiver) = mpsc::sync_channel(BUFFER);
+    thread::spawn(move || {
+        // the loop nest is not `Send`, so it is built on the tracing thread
+        let mut layout = DataLayout::default();
+        let nest = kernel.build(&params, &mut layout).unwrap();
+        let mut tracer = Tracer::new();
+        let mut disconnected = false;
+        tracer.trace(&*nest, &layout, &FxHashSet::default(), |record| {
+            if !disconnected {
+                let item = TraceItem::new(
+                    record.address,
+                    record.reference,
+                    if record.write {
+                        AccessType::Write
+                    } else {
+                        AccessType::Read
+                    },
+                );
+                disconnected = sender.send(item).is_err();
+            }
+        });
+    });
+    Ok(receiver)
+}
@@ -1,197 +1,339 @@
-use crate::cache::{Cache, CacheBlock};
-use crate::virtual_cache::VirtualCache;
+use crate::cache::{CacheBlock, LeaseCache, Writebacks};
+use crate::checkpoint::{Checkpointer, OutputLengths};
+use crate::event_log::{Event, EventLog};
+use crate::index::AddressMapping;
+use crate::leases::LeaseTable;
+use crate::report::{RefReport, SimResult};
+use crate::snapshot::SnapshotWriter;
 use csv::{ReaderBuilder, StringRecord};
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader};
-
-#[derive(Debug)]
-
-pub struct LeaseTable {
-    table: HashMap<u64, (u64, u64, f64)>,
+use std::io::{self, BufRead, BufReader, Read};
+
+/// The random number generator used for lease selection and random eviction. It is seedable
+/// and serializable so that runs can be reproduced and resumed from a checkpoint.
+pub type SimRng = rand_chacha::ChaCha8Rng;
+
+/// The generator for the random choices of the access at `step`: the stream of the run's seed
+/// numbered by the step. The lease and the evicted block of an access therefore depend only on
+/// the seed and the step, not on which accesses were simulated before it, so the sets of a
+/// cache can be simulated in any order.
+pub fn access_rng(rng: &SimRng, step: u64) -> SimRng {
+    let mut access_rng = SimRng::from_seed(rng.get_seed());
+    access_rng.set_stream(step);
+    access_rng
 }
 
-impl LeaseTable {
-    #[allow(dead_code)]
-    pub fn read_lease_look_up_table_from_csv(file_path: &str) -> LeaseTable {
-        let file = File::open(file_path).unwrap();
-        let mut rdr = ReaderBuilder::new().from_reader(file);
-        let mut result: HashMap<u64, (u64, u64, f64)> = HashMap::new();
-
-        for results in rdr.records() {
-            let record = results.expect("Error reading CSV record");
-
-            let access_tag =
-                u64::from_str_radix(&record[0][2..], 16).expect("Error parsing access_tag");
-            let short_lease =
-                u64::from_str_radix(&record[1][2..], 16).expect("Error parsing short_lease");
-            let long_lease =
-                u64::from_str_radix(&record[2][2..], 16).expect("Error parsing long_lease");
-            let short_prob = record[3].parse::<f64>().expect("Error parsing short_prob");
-
-            result.insert(access_tag, (short_lease, long_lease, short_prob));
-        }
-
-        LeaseTable { table: result }
-    }
-
-    pub fn read_lease_look_up_table_from_txt(file_path: &str) -> LeaseTable {
-        let file = File::open(file_path).unwrap();
-        let reader = BufReader::new(file);
-        let mut result: HashMap<u64, (u64, u64, f64)> = HashMap::new();
-
-        let mut lines = reader.lines().skip(2);
-
-        while let Some(Ok(line)) = lines.next() {
-            let parts: Vec<&str> = line.split(',').collect();
-
-            let access_tag =
-                u64::from_str_radix(parts[1].trim_start(), 16).expect("Error parsing access_tag");
-            let short_lease =
-                u64::from_str_radix(parts[2].trim_start(), 16).expect("Error parsing short_lease");
-            let long_lease =
-                u64::from_str_radix(parts[3].trim_start(), 16).expect("Error parsing long_lease");
-            let short_prob = parts[4]
-                .trim()
-                .parse::<f64>()
-                .expect("Error parsing short_prob");
+/// Whether an access loads or stores. Traces without an access type column are all loads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Read,
+    Write,
+}
 
-            result.insert(access_tag, (short_lease, long_lease, short_prob));
+impl AccessType {
+    fn parse(field: Option<&str>) -> AccessType {
+        match field.map(str::trim) {
+            Some("W") | Some("w") => AccessType::Write,
+            _ => AccessType::Read,
         }
-
-        LeaseTable { table: result }
-    }
-
-    pub fn new(filename: &str) -> LeaseTable {
-        LeaseTable::read_lease_look_up_table_from_txt(filename)
-    }
-
-    pub fn query(&self, access_tag: &u64) -> Option<(u64, u64, f64)> {
-        // self.table.get(access_tag).map(|x| *x)
-        self.table.get(access_tag).copied()
     }
 }
 
 pub struct TraceItem {
     pub access_tag: u64,
     pub reference: u64,
-    pub reuse_interval: u64,
+    pub access: AccessType,
 }
 
 impl TraceItem {
-    pub fn new(access_tag: u64, reference: u64, reuse_interval: u64) -> TraceItem {
+    pub fn new(access_tag: u64, reference: u64, access: AccessType) -> TraceItem {
         TraceItem {
             access_tag,
             reference,
-            reuse_interval,
+            access,
         }
     }
 }
 
+/// The first bytes of a binary trace, as written by `trace_gen --format binary`.
+const BINARY_MAGIC: &[u8; 8] = b"CLAMTRC1";
+/// The first bytes of a binary trace with forward reuse intervals (`trace_gen --forward`).
+const FORWARD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC2";
+/// The first bytes of a binary trace with thread IDs (`trace_gen --threads`).
+const THREAD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC3";
+/// The first bytes of a binary trace with forward reuse intervals and thread IDs.
+const FORWARD_THREAD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC4";
+/// reference, reuse interval, address and time as little-endian `u64`, then `b'R'` or `b'W'`
+const BINARY_RECORD_SIZE: usize = 33;
+/// the forward reuse interval comes after the time, then the thread ID, 8 bytes each
+const MAX_BINARY_RECORD_SIZE: usize = 49;
+
+/// Trace paths starting with this prefix name a DACE kernel to trace instead of a file.
+const KERNEL_PREFIX: &str = "kernel:";
+
+/// A trace file, read one access at a time. Text traces are CSV; files starting with
+/// `BINARY_MAGIC` hold fixed-size binary records. With the `trace_gen` feature, a path such as
+/// `kernel:mvt:1024` traces the kernel while the simulation runs.
 pub struct Trace {
-    reader: csv::Reader<BufReader<File>>,
-    current_record: Option<csv::Result<StringRecord>>,
+    source: TraceSource,
+}
+
+enum TraceSource {
+    Text {
+        reader: csv::Reader<BufReader<File>>,
+        current_record: Option<csv::Result<StringRecord>>,
+    },
+    Binary {
+        file: BufReader<File>,
+        /// the size of a record
+        size: usize,
+    },
+    #[cfg(feature = "trace_gen")]
+    Kernel(std::sync::mpsc::Receiver<TraceItem>),
 }
 
 impl Trace {
     pub fn new(file_path: &str) -> io::Result<Self> {
-        let file = File::open(file_path)?;
-        let mut reader = ReaderBuilder::new().from_reader(BufReader::new(file));
+        if let Some(spec) = file_path.strip_prefix(KERNEL_PREFIX) {
+            return Trace::kernel(spec);
+        }
+
+        let mut file = BufReader::new(File::open(file_path)?);
+        let header = file.fill_buf()?;
+        let columns = [
+            (BINARY_MAGIC, false, false),
+            (FORWARD_BINARY_MAGIC, true, false),
+            (THREAD_BINARY_MAGIC, false, true),
+            (FORWARD_THREAD_BINARY_MAGIC, true, true),
+        ]
+        .into_iter()
+        .find(|(magic, _, _)| header.starts_with(*magic));
+        if let Some((magic, forward, thread)) = columns {
+            file.consume(magic.len());
+            let size = BINARY_RECORD_SIZE + 8 * forward as usize + 8 * thread as usize;
+            return Ok(Trace {
+                source: TraceSource::Binary { file, size },
+            });
+        }
+
+        // the access type, forward reuse interval and thread columns are optional, so records
+        // may have four to seven fields; only the access type is used
+        let mut reader = ReaderBuilder::new().flexible(true).from_reader(file);
         let current_record = reader.records().next();
         Ok(Trace {
-            reader,
-            current_record,
+            source: TraceSource::Text {
+                reader,
+                current_record,
+            },
+        })
+    }
+
+    #[cfg(feature = "trace_gen")]
+    fn kernel(spec: &str) -> io::Result<Self> {
+        Ok(Trace {
+            source: TraceSource::Kernel(crate::kernel_trace::trace_kernel(spec)?),
         })
     }
+
+    #[cfg(not(feature = "trace_gen"))]
+    fn kernel(_spec: &str) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tracing kernels requires the trace_gen feature",
+        ))
+    }
+
+    /// Skip the next `count` records without parsing them.
+    pub fn advance(&mut self, count: u64) {
+        match &mut self.source {
+            TraceSource::Text {
+                reader,
+                current_record,
+            } => {
+                for _ in 0..count {
+                    if current_record.is_none() {
+                        break;
+                    }
+                    *current_record = reader.records().next();
+                }
+            }
+            TraceSource::Binary { file, size, .. } => {
+                file.seek_relative(count as i64 * *size as i64)
+                    .expect("Error skipping trace records");
+            }
+            #[cfg(feature = "trace_gen")]
+            TraceSource::Kernel(receiver) => {
+                receiver.iter().take(count as usize).for_each(drop);
+            }
+        }
+    }
 }
 
 impl Iterator for Trace {
     type Item = TraceItem;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let record = match &self.current_record {
-            Some(Ok(record)) => record,
-            Some(Err(_)) | None => return None,
-        };
-
-        let access_tag =
-            u64::from_str_radix(&record[2][2..], 16).expect("Error parsing access_tag");
-        let reference = u64::from_str_radix(&record[0][2..], 16).expect("Error parsing reference");
-        let reuse_interval =
-            u64::from_str_radix(&record[1][2..], 16).expect("Error parsing reuse_interval");
-        let item = TraceItem::new(access_tag, reference, reuse_interval);
-
-        self.current_record = self.reader.records().next();
-
-        Some(item)
+        match &mut self.source {
+            TraceSource::Text {
+                reader,
+                current_record,
+            } => {
+                let record = match current_record {
+                    Some(Ok(record)) => record,
+                    Some(Err(_)) | None => return None,
+                };
+
+                let access_tag =
+                    u64::from_str_radix(&record[2][2..], 16).expect("Error parsing access_tag");
+                let reference =
+                    u64::from_str_radix(&record[0][2..], 16).expect("Error parsing reference");
+                let access = AccessType::parse(record.get(4));
+                let item = TraceItem::new(access_tag, reference, access);
+
+                *current_record = reader.records().next();
+
+                Some(item)
+            }
+            TraceSource::Binary { file, size } => {
+                let mut record = [0; MAX_BINARY_RECORD_SIZE];
+                let record = &mut record[..*size];
+                file.read_exact(record).ok()?;
+                let field =
+                    |i: usize| u64::from_le_bytes(record[i * 8..i * 8 + 8].try_into().unwrap());
+                let access = match record[record.len() - 1] {
+                    b'W' => AccessType::Write,
+                    _ => AccessType::Read,
+                };
+                Some(TraceItem::new(field(2), field(0), access))
+            }
+            #[cfg(feature = "trace_gen")]
+            TraceSource::Kernel(receiver) => receiver.recv().ok(),
+        }
     }
 }
 
 pub fn init_cache_block(
     input: &TraceItem,
-    offset: u64,
-    set: u64,
+    mapping: &AddressMapping,
     table: &LeaseTable,
+    rng: &mut SimRng,
 ) -> Result<CacheBlock, CacheBlock> {
     let mut result = CacheBlock::new();
     result.address = input.access_tag;
-    result.block_offset = input.access_tag & ((1 << offset) - 1);
-    result.set_index = (input.access_tag >> offset) & ((1 << set) - 1);
-    result.tag = input.access_tag >> (offset + set);
+    result.block_offset = mapping.block_offset(input.access_tag);
+    // a skewed physical cache picks among the ways itself; the virtual cache has no conflicts
+    // and always uses the first way's set
+    result.set_index = mapping.set_index(input.access_tag, 0);
+    result.tag = mapping.tag(input.access_tag);
     let lease = table
         .query(&input.reference)
         .expect("Error in query lease for the access");
 
-    let mut random = rand::thread_rng();
-    if random.gen::<f64>() < lease.2 {
+    if rng.gen::<f64>() < lease.2 {
         result.remaining_lease = lease.0;
     } else {
         result.remaining_lease = lease.1;
     }
 
     result.tenancy = 0;
+    result.dirty = input.access == AccessType::Write;
     Ok(result)
 }
 
-pub fn run_trace(mut cache: Cache, trace: Trace, table: &LeaseTable, offset: u64, set: u64) {
-    for trace_item in trace {
-        let result = init_cache_block(&trace_item, offset, set, table);
-        match result {
-            Ok(block) => {
-                cache.update(block);
-                // cache.print("./test.txt").expect("TODO: panic message");
-            }
-            Err(_) => {
-                println!("Error in packing cache block");
-            }
+/// Optional outputs written while a trace is simulated.
+#[derive(Default)]
+pub struct RunOutputs {
+    pub event_log: Option<EventLog>,
+    pub snapshots: Option<SnapshotWriter>,
+    pub checkpoints: Option<Checkpointer>,
+    pub ref_report: Option<RefReport>,
+}
+
+impl RunOutputs {
+    fn flush(&mut self) {
+        if let Some(log) = &mut self.event_log {
+            log.flush().expect("Error writing event log");
+        }
+        if let Some(writer) = &mut self.snapshots {
+            writer.flush().expect("Error writing snapshot");
         }
     }
 
-    println!("Miss ratio: {}", cache.calculate_miss_ratio());
-    println!(
-        "Force Eviction: {} / {} ({})",
-        cache.forced_eviction_counter,
-        cache.step,
-        cache.forced_eviction_counter as f64 / cache.step as f64
-    );
+    /// the lengths of the outputs, as far as they have been flushed
+    fn lengths(&self) -> io::Result<OutputLengths> {
+        Ok(OutputLengths {
+            event_log: self
+                .event_log
+                .as_ref()
+                .map(EventLog::flushed_len)
+                .transpose()?,
+            snapshots: (self.snapshots.as_ref())
+                .map(SnapshotWriter::flushed_len)
+                .transpose()?,
+        })
+    }
 }
 
-pub fn run_trace_virtual(
-    mut cache: VirtualCache,
+fn simulate<C: LeaseCache + Serialize>(
+    cache: &mut C,
     trace: Trace,
     table: &LeaseTable,
-    offset: u64,
-    set: u64,
+    mapping: &AddressMapping,
+    rng: &SimRng,
+    outputs: &mut RunOutputs,
 ) {
     for trace_item in trace {
-        let result = init_cache_block(&trace_item, offset, set, table);
+        let step = cache.step();
+        let mut access_rng = access_rng(rng, step);
+        let result = init_cache_block(&trace_item, mapping, table, &mut access_rng);
         match result {
             Ok(block) => {
-                cache.update(block);
-                // cache
-                //     .print("./testVirtual.txt")
-                //     .expect("TODO: panic message");
+                let result = cache.update(block, &mut access_rng);
+                if let Some(report) = &mut outputs.ref_report {
+                    report.record(trace_item.reference, result.hit);
+                }
+
+                if let Some(log) = &mut outputs.event_log {
+                    let event = Event {
+                        step,
+                        address: trace_item.access_tag,
+                        reference: trace_item.reference,
+                        set_index: block.set_index,
+                        hit: result.hit,
+                        lease: block.remaining_lease,
+                        evictions: result.forced_evictions,
+                    };
+                    log.record(&event).expect("Error writing event log");
+                }
+                if let Some(writer) = &mut outputs.snapshots {
+                    if writer.is_due(cache.step()) {
+                        writer
+                            .record(&cache.snapshot())
+                            .expect("Error writing snapshot");
+                    }
+                }
+                let checkpoint_due = (outputs.checkpoints.as_ref())
+                    .is_some_and(|checkpointer| checkpointer.is_due(cache.step()));
+                if checkpoint_due {
+                    // a run resumed from the checkpoint continues the outputs written so far
+                    outputs.flush();
+                    let lengths = outputs.lengths().expect("Error reading output lengths");
+                    outputs
+                        .checkpoints
+                        .as_ref()
+                        .unwrap()
+                        .save(
+                            cache,
+                            rng,
+                            cache.step(),
+                            outputs.ref_report.as_ref(),
+                            lengths,
+                        )
+                        .expect("Error writing checkpoint");
+                }
             }
             Err(_) => {
                 println!("Error in packing cache block");
@@ -199,36 +341,178 @@ pub fn run_trace_virtual(
         }
     }
 
-    println!("Miss ratio: {}", cache.calculate_miss_ratio());
+    outputs.flush();
 }
-#[allow(unused_variables)]
-pub fn run_trace_virtual_predict(trace: Trace, table: &LeaseTable) {
-    let mut hit: u64 = 0;
+
+pub fn run_trace<C: LeaseCache + Serialize>(
+    mut cache: C,
+    trace: Trace,
+    table: &LeaseTable,
+    mapping: &AddressMapping,
+    rng: &SimRng,
+    outputs: &mut RunOutputs,
+) -> SimResult {
+    simulate(&mut cache, trace, table, mapping, rng, outputs);
+
+    SimResult {
+        accesses: cache.step(),
+        miss_ratio: cache.calculate_miss_ratio(),
+        forced_evictions: cache.forced_evictions(),
+        writebacks: cache.writebacks(),
+        occupancy: None,
+    }
+}
+
+pub fn run_trace_virtual<C: LeaseCache + Serialize>(
+    mut cache: C,
+    trace: Trace,
+    table: &LeaseTable,
+    mapping: &AddressMapping,
+    rng: &SimRng,
+    outputs: &mut RunOutputs,
+) -> SimResult {
+    simulate(&mut cache, trace, table, mapping, rng, outputs);
+
+    SimResult {
+        accesses: cache.step(),
+        miss_ratio: cache.calculate_miss_ratio(),
+        forced_evictions: 0,
+        writebacks: cache.writebacks(),
+        occupancy: None,
+    }
+}
+
+/// Predict the misses of a virtual lease cache with blocks of `offset` bits: an access hits
+/// if the lease given at the previous access to its block has not run out, as in `virtual`.
+pub fn run_trace_virtual_predict(
+    trace: Trace,
+    table: &LeaseTable,
+    offset: u64,
+    rng: &SimRng,
+    outputs: &mut RunOutputs,
+) -> SimResult {
     let mut miss: u64 = 0;
     let mut total: u64 = 0;
+    // the step until which the lease given at the last access to every block covers it
+    let mut covered: HashMap<u64, u64> = HashMap::new();
 
     for trace_item in trace {
         let lease_query = table
             .query(&trace_item.reference)
             .expect("Error in query lease for the access");
 
-        let mut random = rand::thread_rng();
-        let current_lease = if random.gen::<f64>() < lease_query.2 {
+        let current_lease = if access_rng(rng, total).gen::<f64>() < lease_query.2 {
             lease_query.0
         } else {
             lease_query.1
         };
 
-        if trace_item.reuse_interval < current_lease {
-            hit += 1;
-            // println!("HIT Current Lease: {}, Reuse Interval: {}", current_lease, trace_item.reuse_interval);
-        } else {
+        let block = trace_item.access_tag >> offset;
+        let is_hit = covered
+            .insert(block, total.saturating_add(current_lease))
+            .is_some_and(|until| total < until);
+        if !is_hit {
             miss += 1;
-            // println!("MISS Current Lease: {}, Reuse Interval: {}", current_lease, trace_item.reuse_interval);
+        }
+        if let Some(report) = &mut outputs.ref_report {
+            report.record(trace_item.reference, is_hit);
+        }
+
+        if let Some(log) = &mut outputs.event_log {
+            let event = Event {
+                step: total,
+                address: trace_item.access_tag,
+                reference: trace_item.reference,
+                set_index: 0,
+                hit: is_hit,
+                lease: current_lease,
+                evictions: 0,
+            };
+            log.record(&event).expect("Error writing event log");
         }
 
         total += 1;
     }
 
-    println!("Miss ratio: {}", miss as f64 / total as f64);
+    outputs.flush();
+
+    SimResult {
+        accesses: total,
+        miss_ratio: miss as f64 / total as f64,
+        forced_evictions: 0,
+        writebacks: Writebacks::default(),
+        occupancy: None,
+    }
+}
+
+/// Pseudo-random accesses by four references to blocks spread over the address space, a quarter
+/// of them stores, written as a binary trace `name` in the temporary directory, and a lease
+/// table for the references. Traces of the same seed start with the same accesses.
+#[cfg(test)]
+pub fn test_trace(name: &str, accesses: usize, seed: u64) -> (String, LeaseTable) {
+    use crate::leases::Lease;
+    use std::io::Write;
+
+    let path =
+        std::env::temp_dir().join(format!("lease_cache_sim_{}_{}", std::process::id(), name));
+    let mut rng = SimRng::seed_from_u64(seed);
+    let mut bytes = BINARY_MAGIC.to_vec();
+    for time in 0..accesses as u64 {
+        let reference: u64 = rng.gen_range(0..4);
+        let address = (rng.gen_range(0..16u64) << 20 | rng.gen_range(0..64u64)) << 3;
+        for field in [reference, u64::MAX, address, time] {
+            bytes.extend(field.to_le_bytes());
+        }
+        bytes.push(if rng.gen_bool(0.25) { b'W' } else { b'R' });
+    }
+    File::create(&path).unwrap().write_all(&bytes).unwrap();
+
+    let leases = (0..4)
+        .map(|reference| Lease {
+            phase: 0,
+            reference,
+            short_lease: 4 + 8 * reference,
+            long_lease: 160,
+            short_prob: 0.75,
+        })
+        .collect();
+    let path = path.to_str().unwrap().to_string();
+    (path, LeaseTable::from_leases(Vec::new(), leases))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::CacheGeometry;
+    use crate::index::IndexFunction;
+    use crate::virtual_cache::VirtualCache;
+
+    /// `predict` gives the misses of a virtual cache with the same seed and block size.
+    fn check_predict(name: &str, offset: u64) {
+        let (path, table) = test_trace(name, 5000, 3);
+        let geometry = CacheGeometry::from_params(None, Some(1), offset, Some(4)).unwrap();
+        let mapping = AddressMapping::new(IndexFunction::Modulo, &geometry, None).unwrap();
+        let rng = SimRng::seed_from_u64(5);
+        let mut outputs = RunOutputs::default();
+        let cache = VirtualCache::new(&geometry);
+        let trace = Trace::new(&path).unwrap();
+        let simulated = run_trace_virtual(cache, trace, &table, &mapping, &rng, &mut outputs);
+        let trace = Trace::new(&path).unwrap();
+        let predicted = run_trace_virtual_predict(trace, &table, offset, &rng, &mut outputs);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(predicted.accesses, 5000);
+        assert_eq!(predicted.miss_ratio, simulated.miss_ratio);
+    }
+
+    #[test]
+    fn predict_matches_virtual_cache() {
+        check_predict("predict_offset_3", 3);
+    }
+
+    #[test]
+    fn predict_counts_reuse_by_block() {
+        // blocks of four of the trace's addresses
+        check_predict("predict_offset_5", 5);
+    }
 }
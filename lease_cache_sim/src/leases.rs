@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// The encodings of a lease table.
+///
+/// - `text`, as written by the lease generator: header lines, then one
+///   `phase, reference, short_lease, long_lease, short_prob` line per lease, with everything but
+///   the probability in hex.
+/// - `csv`: a `phase,reference,short_lease,long_lease,short_prob` header row, then one row per
+///   lease in the same encoding, optionally with `0x` prefixes. The header lines of the text
+///   format are kept as `# ` comment lines before it. Tables without the phase column are read
+///   as phase 0.
+/// - `json`: an object with the `header` lines and the `leases`, each with the five fields, in
+///   decimal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LeaseFormat {
+    Text,
+    Csv,
+    Json,
+}
+
+impl LeaseFormat {
+    /// The format of a file, by its extension: `.csv`, `.json`, or else text.
+    pub fn of_path(file_path: &str) -> LeaseFormat {
+        match Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            Some("csv") => LeaseFormat::Csv,
+            Some("json") => LeaseFormat::Json,
+            _ => LeaseFormat::Text,
+        }
+    }
+}
+
+/// The leases of one reference in one phase: the short lease is given with probability
+/// `short_prob` and the long lease otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Lease {
+    pub phase: u64,
+    pub reference: u64,
+    pub short_lease: u64,
+    pub long_lease: u64,
+    pub short_prob: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LeaseFile {
+    #[serde(default)]
+    header: Vec<String>,
+    leases: Vec<Lease>,
+}
+
+const CSV_HEADER: [&str; 5] = [
+    "phase",
+    "reference",
+    "short_lease",
+    "long_lease",
+    "short_prob",
+];
+
+/// The leases of every reference, with the header lines and phases of the file they were read
+/// from, so that a table is written back as it was read. Accesses are given the lease of the
+/// last row of their reference, whatever its phase.
+#[derive(Debug)]
+pub struct LeaseTable {
+    header: Vec<String>,
+    leases: Vec<Lease>,
+    table: HashMap<u64, (u64, u64, f64)>,
+}
+
+impl LeaseTable {
+    pub fn from_leases(header: Vec<String>, leases: Vec<Lease>) -> LeaseTable {
+        let table = leases
+            .iter()
+            .map(|lease| {
+                let leases = (lease.short_lease, lease.long_lease, lease.short_prob);
+                (lease.reference, leases)
+            })
+            .collect();
+        LeaseTable {
+            header,
+            leases,
+            table,
+        }
+    }
+
+    /// Load a lease table in the format given by the extension of its path.
+    pub fn load(file_path: &str) -> io::Result<LeaseTable> {
+        LeaseTable::load_as(file_path, LeaseFormat::of_path(file_path))
+    }
+
+    pub fn load_as(file_path: &str, format: LeaseFormat) -> io::Result<LeaseTable> {
+        LeaseTable::read(BufReader::new(File::open(file_path)?), format)
+    }
+
+    /// Save the table; `prefixed` writes the numbers of a CSV table with `0x` prefixes.
+    pub fn save(&self, file_path: &str, format: LeaseFormat, prefixed: bool) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        self.write(&mut writer, format, prefixed)?;
+        writer.flush()
+    }
+
+    pub fn read(mut reader: impl BufRead, format: LeaseFormat) -> io::Result<LeaseTable> {
+        match format {
+            LeaseFormat::Text => read_text(reader),
+            LeaseFormat::Csv => {
+                let mut text = String::new();
+                reader.read_to_string(&mut text)?;
+                read_csv(&text)
+            }
+            LeaseFormat::Json => {
+                let file: LeaseFile = serde_json::from_reader(reader)?;
+                Ok(LeaseTable::from_leases(file.header, file.leases))
+            }
+        }
+    }
+
+    pub fn write(
+        &self,
+        writer: &mut impl Write,
+        format: LeaseFormat,
+        prefixed: bool,
+    ) -> io::Result<()> {
+        match format {
+            LeaseFormat::Text => {
+                for line in &self.header {
+                    writeln!(writer, "{}", line)?;
+                }
+                for lease in &self.leases {
+                    writeln!(
+                        writer,
+                        "{:x}, {:x}, {:x}, {:x}, {}",
+                        lease.phase,
+                        lease.reference,
+                        lease.short_lease,
+                        lease.long_lease,
+                        lease.short_prob
+                    )?;
+                }
+                Ok(())
+            }
+            LeaseFormat::Csv => {
+                for line in &self.header {
+                    writeln!(writer, "# {}", line)?;
+                }
+                let hex = |value: u64| match prefixed {
+                    true => format!("0x{:x}", value),
+                    false => format!("{:x}", value),
+                };
+                let mut csv = csv::Writer::from_writer(writer);
+                csv.write_record(CSV_HEADER)?;
+                for lease in &self.leases {
+                    csv.write_record([
+                        hex(lease.phase),
+                        hex(lease.reference),
+                        hex(lease.short_lease),
+                        hex(lease.long_lease),
+                        lease.short_prob.to_string(),
+                    ])?;
+                }
+                csv.flush()
+            }
+            LeaseFormat::Json => {
+                let file = LeaseFile {
+                    header: self.header.clone(),
+                    leases: self.leases.clone(),
+                };
+                serde_json::to_writer_pretty(&mut *writer, &file)?;
+                writeln!(writer)
+            }
+        }
+    }
+
+    pub fn query(&self, access_tag: &u64) -> Option<(u64, u64, f64)> {
+        self.table.get(access_tag).copied()
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parse a hex number, with or without a `0x` prefix.
+fn parse_hex(field: &str) -> Option<u64> {
+    let field = field.trim();
+    let digits = field
+        .strip_prefix("0x")
+        .or_else(|| field.strip_prefix("0X"))
+        .unwrap_or(field);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Parse a lease from its fields, which start with the phase if there are five of them.
+fn parse_lease(fields: &[&str]) -> Option<Lease> {
+    let (phase, fields) = match fields.len() {
+        5 => (parse_hex(fields[0])?, &fields[1..]),
+        4 => (0, fields),
+        _ => return None,
+    };
+    Some(Lease {
+        phase,
+        reference: parse_hex(fields[0])?,
+        short_lease: parse_hex(fields[1])?,
+        long_lease: parse_hex(fields[2])?,
+        short_prob: fields[3].trim().parse().ok()?,
+    })
+}
+
+/// Read the text format: the lines before the first lease are the header.
+fn read_text(reader: impl BufRead) -> io::Result<LeaseTable> {
+    let mut header = Vec::new();
+    let mut leases = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split(',').collect();
+        match parse_lease(&fields).filter(|_| fields.len() == 5) {
+            Some(lease) => leases.push(lease),
+            None if leases.is_empty() => header.push(line),
+            None if line.trim().is_empty() => {}
+            None => {
+                return Err(invalid(format!(
+                    "line {}: '{}' is not a lease",
+                    number + 1,
+                    line
+                )))
+            }
+        }
+    }
+    Ok(LeaseTable::from_leases(header, leases))
+}
+
+/// Read the CSV format: `#` lines are the header, and a first row that is not a lease names
+/// the columns.
+fn read_csv(text: &str) -> io::Result<LeaseTable> {
+    let header: Vec<String> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix('#'))
+        .map(|line| line.strip_prefix(' ').unwrap_or(line).to_string())
+        .collect();
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes());
+    let mut leases = Vec::new();
+    for (number, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| invalid(e.to_string()))?;
+        let fields: Vec<&str> = record.iter().collect();
+        match parse_lease(&fields) {
+            Some(lease) => leases.push(lease),
+            None if number == 0 => {}
+            None => return Err(invalid(format!("'{}' is not a lease", fields.join(",")))),
+        }
+    }
+    Ok(LeaseTable::from_leases(header, leases))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "\
+Dump predicted miss count (no contention misses): 7320
+Dump formated leases
+0, 0, 3, 0, 1
+0, 2, 1c4, ffffff, 0.9764247727645673
+1, 2, 4, 0, 1
+";
+
+    fn read(text: &str, format: LeaseFormat) -> LeaseTable {
+        LeaseTable::read(text.as_bytes(), format).unwrap()
+    }
+
+    fn write(table: &LeaseTable, format: LeaseFormat, prefixed: bool) -> String {
+        let mut bytes = Vec::new();
+        table.write(&mut bytes, format, prefixed).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    fn assert_same(left: &LeaseTable, right: &LeaseTable) {
+        assert_eq!(left.header, right.header);
+        assert_eq!(left.leases, right.leases);
+    }
+
+    #[test]
+    fn reads_text() {
+        let table = read(TEXT, LeaseFormat::Text);
+        assert_eq!(table.header.len(), 2);
+        assert_eq!(table.leases.len(), 3);
+        assert_eq!(table.leases[1].short_lease, 0x1c4);
+        assert_eq!(table.leases[1].long_lease, 0xffffff);
+        // the last phase of a reference is used
+        assert_eq!(table.query(&2), Some((4, 0, 1.0)));
+        assert_eq!(table.query(&1), None);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let table = read(TEXT, LeaseFormat::Text);
+        assert_eq!(write(&table, LeaseFormat::Text, false), TEXT);
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let table = read(TEXT, LeaseFormat::Text);
+        for prefixed in [false, true] {
+            let csv = write(&table, LeaseFormat::Csv, prefixed);
+            assert_eq!(csv.contains("0x1c4"), prefixed);
+            assert_same(&read(&csv, LeaseFormat::Csv), &table);
+        }
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let table = read(TEXT, LeaseFormat::Text);
+        let json = write(&table, LeaseFormat::Json, false);
+        assert_same(&read(&json, LeaseFormat::Json), &table);
+    }
+
+    #[test]
+    fn reads_csv_without_phases() {
+        let csv =
+            "access_tag,short_lease,long_lease,short_prob\n0x0,0x3,0x0,1\n0x2,0x1c4,0xffffff,0.5\n";
+        let table = read(csv, LeaseFormat::Csv);
+        assert!(table.header.is_empty());
+        assert_eq!(table.leases[1].phase, 0);
+        assert_eq!(table.query(&2), Some((0x1c4, 0xffffff, 0.5)));
+    }
+
+    #[test]
+    fn rejects_malformed_leases() {
+        assert!(
+            LeaseTable::read(format!("{}0, 3, x\n", TEXT).as_bytes(), LeaseFormat::Text).is_err()
+        );
+        assert!(LeaseTable::read("phase\n0,1,2\n".as_bytes(), LeaseFormat::Csv).is_err());
+    }
+
+    #[test]
+    fn detects_formats_by_extension() {
+        assert_eq!(LeaseFormat::of_path("leases.CSV"), LeaseFormat::Csv);
+        assert_eq!(LeaseFormat::of_path("out/leases.json"), LeaseFormat::Json);
+        assert_eq!(
+            LeaseFormat::of_path("3mm_output_shel_leases"),
+            LeaseFormat::Text
+        );
+    }
+}
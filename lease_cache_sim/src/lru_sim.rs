@@ -1,4 +1,8 @@
-use crate::lease_table::Trace;
+use crate::cache::Writebacks;
+use crate::geometry::CacheGeometry;
+use crate::index::AddressMapping;
+use crate::lease_table::{AccessType, Trace};
+use crate::report::SimResult;
 use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::io::Write;
@@ -8,6 +12,8 @@ pub struct LRUCacheBlock {
     pub tag: u64,
     pub set_index: u64,
     pub valid: bool,
+    pub last_access: u64,
+    pub dirty: bool,
 }
 
 impl LRUCacheBlock {
@@ -16,6 +22,8 @@ impl LRUCacheBlock {
             tag,
             set_index,
             valid: true,
+            last_access: 0,
+            dirty: false,
         }
     }
 
@@ -32,10 +40,12 @@ impl LRUCacheBlock {
 pub struct LRUCache {
     size: usize,
     sets: Vec<VecDeque<LRUCacheBlock>>,
-    cache_map: HashMap<u64, (usize, usize)>, // (set_index, position in VecDeque)
+    cache_map: HashMap<(usize, u64), usize>, // (set_index, tag) -> position in VecDeque
     num_sets: usize,
     associativity: usize,
     miss_counter: u64,
+    time: u64,
+    writeback_counter: u64,
 }
 
 impl LRUCache {
@@ -48,20 +58,21 @@ impl LRUCache {
             num_sets,
             associativity,
             miss_counter: 0,
+            time: 0,
+            writeback_counter: 0,
         }
     }
 
-    pub fn access(&mut self, tag: u64, set_index: usize) {
+    pub fn access(&mut self, tag: u64, set_index: usize, write: bool) {
         if set_index >= self.num_sets {
             panic!("set_index out of bounds");
         }
 
-        if let Some(&(stored_set_index, pos)) = self.cache_map.get(&tag) {
-            if stored_set_index == set_index {
-                // Cache hit
-                if let Some(block) = self.sets[set_index].remove(pos) {
-                    self.sets[set_index].push_front(block);
-                }
+        if let Some(&pos) = self.cache_map.get(&(set_index, tag)) {
+            // Cache hit
+            if let Some(mut block) = self.sets[set_index].remove(pos) {
+                block.dirty |= write;
+                self.sets[set_index].push_front(block);
             }
         } else {
             // Cache miss
@@ -69,18 +80,54 @@ impl LRUCache {
             if self.sets[set_index].len() == self.associativity {
                 // Evict the least recently used block
                 if let Some(lru_block) = self.sets[set_index].pop_back() {
-                    self.cache_map.remove(&lru_block.tag);
+                    self.cache_map.remove(&(set_index, lru_block.tag));
+                    self.writeback_counter += lru_block.dirty as u64;
                 }
             }
-            let new_block = LRUCacheBlock::new(tag, set_index as u64);
+            let mut new_block = LRUCacheBlock::new(tag, set_index as u64);
+            new_block.dirty = write;
             self.sets[set_index].push_front(new_block);
-            self.cache_map.insert(tag, (set_index, 0));
+            self.cache_map.insert((set_index, tag), 0);
         }
 
         // Update cache_map with new positions
         for (i, block) in self.sets[set_index].iter().enumerate() {
-            self.cache_map.insert(block.tag, (set_index, i));
+            self.cache_map.insert((set_index, block.tag), i);
+        }
+    }
+
+    /// Access a skewed-associative cache, where every set is a single line and `candidates`
+    /// holds the line each way's hash selects. On a miss the least recently used candidate is
+    /// replaced.
+    pub fn access_skewed(&mut self, tag: u64, candidates: &[usize], write: bool) {
+        self.time += 1;
+
+        if let Some(&set_index) = candidates
+            .iter()
+            .find(|&&index| self.cache_map.contains_key(&(index, tag)))
+        {
+            // Cache hit
+            self.sets[set_index][0].last_access = self.time;
+            self.sets[set_index][0].dirty |= write;
+            return;
+        }
+
+        // Cache miss
+        self.miss_counter += 1;
+        let victim = candidates
+            .iter()
+            .copied()
+            .min_by_key(|&index| self.sets[index].front().map_or(0, |b| b.last_access))
+            .expect("no candidate set");
+        if let Some(lru_block) = self.sets[victim].pop_back() {
+            self.cache_map.remove(&(victim, lru_block.tag));
+            self.writeback_counter += lru_block.dirty as u64;
         }
+        let mut new_block = LRUCacheBlock::new(tag, victim as u64);
+        new_block.last_access = self.time;
+        new_block.dirty = write;
+        self.sets[victim].push_front(new_block);
+        self.cache_map.insert((victim, tag), 0);
     }
 
     #[allow(dead_code)]
@@ -114,24 +161,49 @@ impl LRUCache {
 
 pub fn run_lru_simulation(
     trace: Trace,
-    cache_size: usize,
-    num_sets: usize,
-    associativity: usize,
-    offset: u64,
-    set: u64,
-) {
-    let mut lru_cache = LRUCache::new(cache_size, num_sets, associativity);
+    geometry: &CacheGeometry,
+    mapping: &AddressMapping,
+) -> SimResult {
+    let cache_size = geometry.capacity_blocks() as usize;
+    let num_sets = geometry.sets as usize;
+    let associativity = geometry.ways as usize;
+    let mut lru_cache = if mapping.is_skewed() {
+        LRUCache::new(cache_size, cache_size, 1)
+    } else {
+        LRUCache::new(cache_size, num_sets, associativity)
+    };
     let mut total_accesses = 0;
 
     for trace_item in trace {
-        let set_index = (trace_item.access_tag >> offset) & ((1 << set) - 1);
-        lru_cache.access(trace_item.access_tag, set_index as usize);
+        let write = trace_item.access == AccessType::Write;
+        if mapping.is_skewed() {
+            let candidates: Vec<usize> = (0..associativity as u64)
+                .map(|way| {
+                    (way * num_sets as u64 + mapping.set_index(trace_item.access_tag, way)) as usize
+                })
+                .collect();
+            lru_cache.access_skewed(mapping.tag(trace_item.access_tag), &candidates, write);
+        } else {
+            let set_index = mapping.set_index(trace_item.access_tag, 0);
+            lru_cache.access(
+                mapping.tag(trace_item.access_tag),
+                set_index as usize,
+                write,
+            );
+        }
         total_accesses += 1;
     }
 
     // lru_cache.print("lru_cache_output.txt").unwrap();
-    println!(
-        "Miss ratio: {}",
-        lru_cache.calculate_miss_ratio(total_accesses)
-    );
+    let writebacks = Writebacks {
+        expiry: 0,
+        eviction: lru_cache.writeback_counter,
+    };
+    SimResult {
+        accesses: total_accesses,
+        miss_ratio: lru_cache.calculate_miss_ratio(total_accesses),
+        forced_evictions: 0,
+        writebacks,
+        occupancy: None,
+    }
 }
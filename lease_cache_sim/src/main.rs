@@ -1,15 +1,48 @@
 use std::time::Instant;
 
-use clap::Parser;
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use rand::SeedableRng;
+use serde::de::DeserializeOwned;
 
+use batch::{run_batch, ExperimentSpec};
 use cache::Cache;
-use lease_table::{run_trace, run_trace_virtual, run_trace_virtual_predict, LeaseTable, Trace};
+use checkpoint::{Checkpoint, Checkpointer, OutputLengths, RunSettings};
+use event_log::{diff_event_logs, EventLog, EventLogReader};
+use fully_associative::FullyAssociativeCache;
+use geometry::{CacheGeometry, Capacity};
+use index::{AddressMapping, IndexFunction};
+use lease_table::{
+    run_trace, run_trace_virtual, run_trace_virtual_predict, RunOutputs, SimRng, Trace,
+};
+use leases::{LeaseFormat, LeaseTable};
 use lru_sim::run_lru_simulation;
+use model::{run_lease_model, run_lru_model, LruModel, ReuseProfile};
+use parallel::run_trace_parallel;
+use ref_labels::RefLabels;
+use report::{append_rows, RefReport, ResultRow, SimResult};
+use snapshot::SnapshotWriter;
+use stack_distance::stack_distances;
 use virtual_cache::VirtualCache;
 
+mod batch;
 mod cache;
+mod checkpoint;
+mod event_log;
+mod fully_associative;
+mod geometry;
+mod index;
+#[cfg(feature = "trace_gen")]
+mod kernel_trace;
 mod lease_table;
+mod leases;
 mod lru_sim;
+mod model;
+mod parallel;
+mod ref_labels;
+mod report;
+mod snapshot;
+mod stack_distance;
 mod virtual_cache;
 
 #[derive(Parser)]
@@ -20,101 +53,800 @@ mod virtual_cache;
     about = "Cache Lease Assignment Model Simulator"
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
     /// The path of trace file
     #[arg(
         short,
         long,
+        global = true,
         value_name = "TRACE_FILE",
         default_value = "testInput/3mm_output.txt"
     )]
     trace: String,
 
-    /// The path of lease table file
+    /// The path of lease table file (physical, virtual, predict and lease-model), in the text,
+    /// CSV or JSON format given by its extension
     #[arg(
         short,
         long,
+        global = true,
         value_name = "LEASE_TABLE_FILE",
         default_value = "testInput/3mm_output_shel_leases"
     )]
     lease_table: String,
 
-    /// The mode of the simulator: 0 for physical, 1 for virtual, 2 for virtual with prediction, 3 for LRU
-    #[arg(short, long, value_name = "MODE", default_value = "0")]
-    mode: u64,
+    /// Append the result of the run, with its parameters, to this CSV file
+    #[arg(long, global = true, value_name = "RESULTS_FILE")]
+    output: Option<String>,
+
+    /// The reference metadata written by trace_gen, to name references in per-reference reports
+    #[arg(long, global = true, value_name = "REF_FILE")]
+    ref_labels: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Simulate a physical lease cache, which evicts a random block when a set is full
+    Physical {
+        /// The cache size, in blocks (e.g. 128) or bytes (e.g. 1024B, 32KB)
+        #[arg(short, long, value_name = "CACHE_SIZE")]
+        cache_size: Option<Capacity>,
+
+        /// The associativity (number of ways) of the cache
+        #[arg(short, long, value_name = "ASSOCIATIVITY")]
+        associativity: Option<u64>,
+
+        #[command(flatten)]
+        address: AddressArgs,
+
+        #[command(flatten)]
+        lease: LeaseArgs,
+
+        #[command(flatten)]
+        recording: RecordingArgs,
+
+        /// Simulate the sets on this many threads
+        #[arg(
+            long,
+            value_name = "THREADS",
+            conflicts_with_all = [
+                "event_log",
+                "ref_report",
+                "snapshot_every",
+                "checkpoint_every",
+                "resume"
+            ]
+        )]
+        threads: Option<usize>,
+    },
+    /// Simulate a virtual lease cache, whose sets hold every block with an unexpired lease
+    Virtual {
+        #[command(flatten)]
+        address: AddressArgs,
+
+        /// The physical cache size, in blocks or bytes, that snapshots compare the virtual cache
+        /// size against
+        #[arg(long, value_name = "CACHE_SIZE")]
+        capacity: Option<Capacity>,
+
+        #[command(flatten)]
+        lease: LeaseArgs,
+
+        #[command(flatten)]
+        recording: RecordingArgs,
+
+        /// Simulate the sets on this many threads
+        #[arg(
+            long,
+            value_name = "THREADS",
+            conflicts_with_all = [
+                "event_log",
+                "ref_report",
+                "snapshot_every",
+                "checkpoint_every",
+                "resume"
+            ]
+        )]
+        threads: Option<usize>,
+    },
+    /// Predict the misses of a virtual lease cache from the reuse intervals of its blocks
+    Predict {
+        /// The length of the block offset in bits
+        #[arg(
+            short,
+            long,
+            value_name = "OFFSET",
+            default_value = "3",
+            value_parser = clap::value_parser!(u64).range(..32)
+        )]
+        offset: u64,
+
+        #[command(flatten)]
+        lease: LeaseArgs,
+    },
+    /// Simulate an LRU cache of the same geometry, for comparison
+    Lru {
+        /// The cache size, in blocks (e.g. 128) or bytes (e.g. 1024B, 32KB)
+        #[arg(short, long, value_name = "CACHE_SIZE")]
+        cache_size: Option<Capacity>,
+
+        /// The associativity (number of ways) of the cache
+        #[arg(short, long, value_name = "ASSOCIATIVITY")]
+        associativity: Option<u64>,
+
+        #[command(flatten)]
+        address: AddressArgs,
+    },
+    /// Predict the miss ratio of an LRU cache of the same size with the average eviction time
+    /// model
+    Aet {
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Predict the miss ratio of an LRU cache of the same size with the footprint model
+    Footprint {
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Predict the miss ratio and occupancy of a virtual lease cache from the lease
+    /// probabilities, without drawing leases
+    LeaseModel {
+        /// The length of the block offset in bits
+        #[arg(
+            short,
+            long,
+            value_name = "OFFSET",
+            default_value = "3",
+            value_parser = clap::value_parser!(u64).range(..32)
+        )]
+        offset: u64,
+    },
+    /// Run every combination of benchmarks, modes, geometries and seeds listed in an
+    /// experiment file, in parallel, and write the results to one table
+    Batch {
+        /// The TOML experiment specification
+        spec: String,
+    },
+    /// Compute the LRU stack distance histograms of the trace and the miss ratio curve of a
+    /// fully associative LRU cache
+    StackDistance {
+        /// The length of the block offset in bits
+        #[arg(
+            short,
+            long,
+            value_name = "OFFSET",
+            default_value = "3",
+            value_parser = clap::value_parser!(u64).range(..32)
+        )]
+        offset: u64,
+
+        /// The path of the stack distance histogram
+        #[arg(long, value_name = "HIST_FILE", default_value = "stack_distance.csv")]
+        histogram: String,
+
+        /// Also write the stack distance histogram of every reference to this file
+        #[arg(long, value_name = "HIST_FILE")]
+        ref_histogram: Option<String>,
+
+        /// The path of the miss ratio curve
+        #[arg(long, value_name = "MRC_FILE", default_value = "mrc.csv")]
+        mrc: String,
+    },
+    /// Convert a lease table between the text, CSV and JSON formats, keeping its header lines
+    /// and phases
+    ConvertLeases {
+        /// The lease table to convert
+        input: String,
+        /// The path of the converted table
+        output: String,
+
+        /// The format of the input, by default given by its extension
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        from: Option<LeaseFormat>,
+
+        /// The format of the output, by default given by its extension
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        to: Option<LeaseFormat>,
+
+        /// Write the numbers of a CSV table with 0x prefixes
+        #[arg(long)]
+        hex_prefix: bool,
+    },
+    /// Compare two event logs and report where they diverge
+    Diff {
+        /// The event log used as the reference
+        left: String,
+        /// The event log compared against it
+        right: String,
+    },
+}
+
+/// How addresses are split into block offset, set index and tag.
+#[derive(Args)]
+struct AddressArgs {
+    /// The length of the block offset in bits
+    #[arg(short, long, value_name = "OFFSET", default_value = "3")]
+    offset: u64,
+
+    /// The length of set index in bits
+    #[arg(short, long, value_name = "SET")]
+    set: Option<u64>,
+
+    /// The function mapping a block address to its set
+    #[arg(long, value_enum, value_name = "FUNCTION", default_value = "modulo")]
+    index: IndexFunction,
+
+    /// The block address bits used as set index by the mask index function, in hex
+    #[arg(long, value_name = "MASK", value_parser = parse_hex)]
+    index_mask: Option<u64>,
+}
 
-    /// The associativity of the cache
-    #[arg(short, long, value_name = "ASSOCIATIVITY", default_value = "128")]
-    associativity: u64,
+/// Options of the LRU miss ratio models, which predict a fully associative cache of the given
+/// size.
+#[derive(Args)]
+struct ModelArgs {
+    /// The cache size, in blocks (e.g. 128) or bytes (e.g. 1024B, 32KB)
+    #[arg(short, long, value_name = "CACHE_SIZE")]
+    cache_size: Option<Capacity>,
 
-    /// The length of the block offset
+    /// The length of the block offset in bits
     #[arg(short, long, value_name = "OFFSET", default_value = "3")]
     offset: u64,
 
-    /// The length of set index
-    #[arg(short, long, value_name = "SET", default_value = "7")]
-    set: u64,
+    /// Also write the predicted miss ratio of every cache size to this file
+    #[arg(long, value_name = "MRC_FILE")]
+    mrc: Option<String>,
+}
+
+/// Options of every mode that assigns leases from the lease table.
+#[derive(Args)]
+struct LeaseArgs {
+    /// Seed of the random number generator used for leases and evictions
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// Write a per-access event log to this file
+    #[arg(short, long, value_name = "EVENT_LOG_FILE")]
+    event_log: Option<String>,
+
+    /// Write the accesses, misses and leases of every reference to this CSV file
+    #[arg(long, value_name = "REPORT_FILE")]
+    ref_report: Option<String>,
+}
+
+/// Options of the modes that simulate a cache step by step.
+#[derive(Args)]
+struct RecordingArgs {
+    /// Record the cache state every N accesses
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    snapshot_every: Option<u64>,
+
+    /// The path of the snapshot time-series CSV
+    #[arg(long, value_name = "SNAPSHOT_FILE", default_value = "snapshots.csv")]
+    snapshot_output: String,
+
+    /// Save a checkpoint every N accesses
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    checkpoint_every: Option<u64>,
+
+    /// The path of the checkpoint file
+    #[arg(
+        long,
+        value_name = "CHECKPOINT_FILE",
+        default_value = "checkpoint.json"
+    )]
+    checkpoint: String,
+
+    /// Continue a simulation from a checkpoint file
+    #[arg(long, value_name = "CHECKPOINT_FILE")]
+    resume: Option<String>,
+}
+
+fn parse_hex(value: &str) -> Result<u64, String> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+/// Parse the command line. An option that belongs to a different mode is reported together with
+/// the modes that do accept it, instead of only as an unexpected argument.
+fn parse_cli() -> Cli {
+    Cli::try_parse().unwrap_or_else(|e| {
+        if e.kind() == ErrorKind::UnknownArgument {
+            if let Some(ContextValue::String(arg)) = e.get(ContextKind::InvalidArg) {
+                let modes = modes_accepting(arg);
+                if !modes.is_empty() {
+                    let _ = e.print();
+                    eprintln!("note: '{}' applies to: {}", arg, modes.join(", "));
+                    std::process::exit(2);
+                }
+            }
+        }
+        e.exit()
+    })
+}
+
+/// the subcommands that have an option spelled `arg`, as `--name`, `--name=value` or `-n`
+fn modes_accepting(arg: &str) -> Vec<String> {
+    let flag = arg.split('=').next().unwrap_or(arg);
+    Cli::command()
+        .get_subcommands()
+        .filter(|mode| {
+            mode.get_arguments().any(|option| {
+                option
+                    .get_long()
+                    .map(|long| format!("--{}", long))
+                    .as_deref()
+                    == Some(flag)
+                    || option
+                        .get_short()
+                        .map(|short| format!("-{}", short))
+                        .as_deref()
+                        == Some(flag)
+            })
+        })
+        .map(|mode| mode.get_name().to_string())
+        .collect()
+}
+
+fn cache_geometry(
+    capacity: Option<Capacity>,
+    associativity: Option<u64>,
+    address: &AddressArgs,
+) -> (CacheGeometry, AddressMapping) {
+    let geometry = CacheGeometry::from_params(capacity, associativity, address.offset, address.set)
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid cache geometry: {}", e);
+            std::process::exit(1);
+        });
+    let mapping =
+        AddressMapping::new(address.index, &geometry, address.index_mask).unwrap_or_else(|e| {
+            eprintln!("Invalid set index: {}", e);
+            std::process::exit(1);
+        });
+    (geometry, mapping)
+}
+
+/// The parallel engine splits the cache by set, which needs more than one set and a set
+/// index that does not depend on the way.
+fn check_parallel(threads: Option<usize>, geometry: &CacheGeometry, mapping: &AddressMapping) {
+    if threads.is_some() && (geometry.sets == 1 || mapping.is_skewed()) {
+        eprintln!("--threads needs a cache with more than one set and an index other than skewed");
+        std::process::exit(1);
+    }
+}
+
+fn seeded_rng(seed: Option<u64>) -> SimRng {
+    match seed {
+        Some(seed) => SimRng::seed_from_u64(seed),
+        None => SimRng::from_entropy(),
+    }
+}
+
+/// The outputs of a run, continuing those of the run it resumes, which had written `resumed`
+/// when its checkpoint was taken. Checkpoints are set up by `restore`.
+fn run_outputs(
+    lease: &LeaseArgs,
+    recording: Option<&RecordingArgs>,
+    capacity: Option<u64>,
+    resumed: Option<OutputLengths>,
+) -> RunOutputs {
+    RunOutputs {
+        event_log: lease.event_log.as_ref().map(|path| {
+            match resumed {
+                Some(lengths) => EventLog::resume(path, lengths.event_log.unwrap_or(0)),
+                None => EventLog::create(path),
+            }
+            .expect("Error creating event log file")
+        }),
+        snapshots: recording.and_then(|recording| {
+            recording.snapshot_every.map(|interval| {
+                let path = &recording.snapshot_output;
+                match resumed {
+                    Some(lengths) => {
+                        let len = lengths.snapshots.unwrap_or(0);
+                        SnapshotWriter::resume(path, interval, capacity, len)
+                    }
+                    None => SnapshotWriter::create(path, interval, capacity),
+                }
+                .expect("Error creating snapshot file")
+            })
+        }),
+        checkpoints: None,
+        ref_report: lease.ref_report.as_ref().map(|_| RefReport::default()),
+    }
+}
+
+/// Write the per-reference report requested by `--ref-report`, if any.
+fn write_ref_report(
+    outputs: &RunOutputs,
+    lease: &LeaseArgs,
+    table: &LeaseTable,
+    labels: Option<&RefLabels>,
+) {
+    if let (Some(report), Some(path)) = (&outputs.ref_report, &lease.ref_report) {
+        report
+            .write(path, table, labels)
+            .expect("Error writing reference report");
+    }
+}
+
+/// Restore the cache, random number generator and trace position from `--resume`, or start a
+/// new simulation with `fresh` if no checkpoint was given, and open the run's `outputs`, given
+/// the output lengths of the checkpoint, and its checkpoints.
+fn restore<C: DeserializeOwned>(
+    recording: &RecordingArgs,
+    settings: &RunSettings,
+    trace: &mut Trace,
+    rng: &mut SimRng,
+    outputs: impl FnOnce(Option<OutputLengths>) -> RunOutputs,
+    fresh: impl FnOnce() -> C,
+) -> (C, RunOutputs) {
+    let checkpointer = recording
+        .checkpoint_every
+        .map(|interval| Checkpointer::new(&recording.checkpoint, interval, settings.clone()));
+    let Some(path) = &recording.resume else {
+        let mut outputs = outputs(None);
+        outputs.checkpoints = checkpointer;
+        return (fresh(), outputs);
+    };
 
-    /// The cache size
-    #[arg(short, long, value_name = "CACHE_SIZE", default_value = "128")]
-    cache_size: u64,
+    let checkpoint: Checkpoint<C> = Checkpoint::load(path, settings).unwrap_or_else(|e| {
+        eprintln!("Cannot resume from {}: {}", path, e);
+        std::process::exit(1);
+    });
+    println!("Resuming from access {}", checkpoint.position);
+    trace.advance(checkpoint.position);
+    *rng = checkpoint.rng;
+    let mut outputs = outputs(Some(checkpoint.outputs));
+    outputs.checkpoints = checkpointer;
+    if outputs.ref_report.is_some() {
+        // the report covers the whole run, so it continues the counts of the checkpoint
+        outputs.ref_report = Some(checkpoint.ref_report.unwrap_or_else(|| {
+            eprintln!(
+                "Cannot resume from {}: the checkpoint has no per-reference counts for \
+                 --ref-report, as its run did not write one",
+                path
+            );
+            std::process::exit(1);
+        }));
+    }
+    (checkpoint.cache, outputs)
+}
+
+fn print_geometry(geometry: &CacheGeometry, mapping: &AddressMapping) {
+    print!("Cache Geometry: {}  ", geometry);
+    print!("Offset: {}  ", geometry.offset_bits());
+    println!("Set: {}", geometry.set_bits());
+    println!("Set Index: {:?}", mapping.function);
 }
 
 fn main() {
-    let cli = Cli::parse();
+    let cli = parse_cli();
+
+    let labels = cli.ref_labels.as_ref().map(|path| {
+        RefLabels::load(path).unwrap_or_else(|e| {
+            eprintln!("Cannot read reference metadata {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+
+    if let Command::Diff { left, right } = &cli.command {
+        let left = EventLogReader::new(left).expect("Error loading left event log");
+        let right = EventLogReader::new(right).expect("Error loading right event log");
+        diff_event_logs(left, right).print_report(labels.as_ref());
+        return;
+    }
+
+    if let Command::ConvertLeases {
+        input,
+        output,
+        from,
+        to,
+        hex_prefix,
+    } = &cli.command
+    {
+        let from = from.unwrap_or_else(|| LeaseFormat::of_path(input));
+        let to = to.unwrap_or_else(|| LeaseFormat::of_path(output));
+        let table = LeaseTable::load_as(input, from).unwrap_or_else(|e| {
+            eprintln!("Cannot read lease table {}: {}", input, e);
+            std::process::exit(1);
+        });
+        if let Err(e) = table.save(output, to, *hex_prefix) {
+            eprintln!("Cannot write lease table {}: {}", output, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::Batch { spec } = &cli.command {
+        let spec = ExperimentSpec::load(spec).unwrap_or_else(|e| {
+            eprintln!("Cannot read experiment {}: {}", spec, e);
+            std::process::exit(1);
+        });
+        let output = cli.output.as_deref().unwrap_or(&spec.output);
+        if let Err(e) = run_batch(&spec, output) {
+            eprintln!("Batch failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Command::StackDistance {
+        offset,
+        histogram,
+        ref_histogram,
+        mrc,
+    } = &cli.command
+    {
+        let trace = Trace::new(&cli.trace).expect("Error loading trace file");
+        println!("Trace Path: {}", cli.trace);
+        let start = Instant::now();
+        let histograms = stack_distances(trace, *offset);
+        println!("Accesses: {}", histograms.global.accesses());
+        println!("Blocks: {}", histograms.blocks);
+        println!("Cold misses: {}", histograms.global.cold);
+        histograms
+            .write_histogram(histogram)
+            .expect("Error writing stack distance histogram");
+        if let Some(path) = ref_histogram {
+            histograms
+                .write_ref_histograms(path)
+                .expect("Error writing stack distance histograms");
+        }
+        histograms
+            .write_miss_ratio_curve(mrc)
+            .expect("Error writing miss ratio curve");
+        println!("Time elapsed is: {:?}", start.elapsed());
+        return;
+    }
 
     let trace_path = &cli.trace;
     let lease_table_path = &cli.lease_table;
 
-    let test_table = LeaseTable::new(lease_table_path);
-    let test_trace = Trace::new(trace_path).expect("Error loading trace file");
+    let (mode, geometry, mapping, lease) = match &cli.command {
+        // the models predict a fully associative cache, which has a single set
+        Command::Aet { model } | Command::Footprint { model } => {
+            let address = AddressArgs {
+                offset: model.offset,
+                set: Some(0),
+                index: IndexFunction::Modulo,
+                index_mask: None,
+            };
+            let (geometry, mapping) = cache_geometry(model.cache_size, None, &address);
+            let mode = match &cli.command {
+                Command::Aet { .. } => "aet",
+                _ => "footprint",
+            };
+            (mode, Some(geometry), Some(mapping), None)
+        }
+        Command::LeaseModel { .. } => ("lease-model", None, None, None),
+        Command::Physical {
+            cache_size,
+            associativity,
+            address,
+            lease,
+            threads,
+            ..
+        } => {
+            let (geometry, mapping) = cache_geometry(*cache_size, *associativity, address);
+            check_parallel(*threads, &geometry, &mapping);
+            ("physical", Some(geometry), Some(mapping), Some(lease))
+        }
+        // a virtual cache has as many ways as it needs, so only the set count is fixed
+        Command::Virtual {
+            address,
+            lease,
+            threads,
+            ..
+        } => {
+            let (geometry, mapping) = cache_geometry(None, Some(1), address);
+            check_parallel(*threads, &geometry, &mapping);
+            ("virtual", Some(geometry), Some(mapping), Some(lease))
+        }
+        Command::Predict { lease, .. } => ("predict", None, None, Some(lease)),
+        Command::Lru {
+            cache_size,
+            associativity,
+            address,
+        } => {
+            let (geometry, mapping) = cache_geometry(*cache_size, *associativity, address);
+            ("lru", Some(geometry), Some(mapping), None)
+        }
+        Command::Batch { .. }
+        | Command::Diff { .. }
+        | Command::StackDistance { .. }
+        | Command::ConvertLeases { .. } => unreachable!("handled above"),
+    };
 
-    let associativity = cli.associativity;
-    let cache_size = cli.cache_size;
-    let offset = cli.offset;
-    let set = cli.set;
-    let num_sets = 1 << set; // Calculate the number of sets based on the set index bits
-    let mode = cli.mode;
+    let uses_leases = lease.is_some() || matches!(cli.command, Command::LeaseModel { .. });
+    let test_table = uses_leases.then(|| {
+        LeaseTable::load(lease_table_path).unwrap_or_else(|e| {
+            eprintln!("Cannot read lease table {}: {}", lease_table_path, e);
+            std::process::exit(1);
+        })
+    });
+    let mut test_trace = Trace::new(trace_path).expect("Error loading trace file");
 
     print!("Current Parameters:");
     println!("Trace Path: {}", trace_path);
-    println!("Lease Table Path: {}", lease_table_path);
-    print!("Associativity: {}  ", associativity);
-    print!("Cache Size: {}  ", cache_size);
-    print!("Offset: {}  ", offset);
-    print!("Set: {}  ", set);
-    println!("Number of Sets: {}", num_sets); // Print the number of sets
+    if uses_leases {
+        println!("Lease Table Path: {}", lease_table_path);
+    }
+    if let (Some(geometry), Some(mapping)) = (&geometry, &mapping) {
+        print_geometry(geometry, mapping);
+    }
+    if let Command::Predict { offset, .. } | Command::LeaseModel { offset } = &cli.command {
+        println!("Offset: {}", offset);
+    }
     println!("Running Mode: {}", mode);
 
+    let seed = lease.and_then(|lease| lease.seed);
+    let mut rng = seeded_rng(seed);
+
     let start = Instant::now(); // Start timing
 
-    match mode {
-        0 => {
-            let test_cache = Cache::new(cache_size, associativity);
-            run_trace(test_cache, test_trace, &test_table, offset, set);
+    let result: SimResult = match &cli.command {
+        Command::Physical {
+            lease,
+            recording,
+            threads,
+            ..
+        } => {
+            let geometry = geometry.unwrap();
+            let mapping = mapping.unwrap();
+            let table = test_table.as_ref().unwrap();
+            let capacity = geometry.capacity_blocks();
+            let outputs = |resumed| run_outputs(lease, Some(recording), Some(capacity), resumed);
+            let settings = RunSettings {
+                mode: mode.to_string(),
+                trace: trace_path.to_string(),
+                geometry,
+                mapping,
+                seed: lease.seed,
+            };
+            let (result, outputs) = if let Some(threads) = *threads {
+                let ways = Some(geometry.ways);
+                let result =
+                    run_trace_parallel(test_trace, table, &geometry, &mapping, ways, &rng, threads);
+                (result, RunOutputs::default())
+            } else if geometry.sets == 1 {
+                let (test_cache, mut outputs) = restore(
+                    recording,
+                    &settings,
+                    &mut test_trace,
+                    &mut rng,
+                    outputs,
+                    || FullyAssociativeCache::new(Some(capacity)),
+                );
+                let result = run_trace(test_cache, test_trace, table, &mapping, &rng, &mut outputs);
+                (result, outputs)
+            } else {
+                let (test_cache, mut outputs) = restore(
+                    recording,
+                    &settings,
+                    &mut test_trace,
+                    &mut rng,
+                    outputs,
+                    || Cache::new(&geometry, &mapping),
+                );
+                let result = run_trace(test_cache, test_trace, table, &mapping, &rng, &mut outputs);
+                (result, outputs)
+            };
+            write_ref_report(&outputs, lease, table, labels.as_ref());
+            result
         }
-        1 => {
-            let test_cache = VirtualCache::new(associativity);
-            run_trace_virtual(test_cache, test_trace, &test_table, offset, set);
+        Command::Virtual {
+            capacity,
+            lease,
+            recording,
+            threads,
+            ..
+        } => {
+            let geometry = geometry.unwrap();
+            let mapping = mapping.unwrap();
+            let table = test_table.as_ref().unwrap();
+            let capacity = capacity.map(|capacity| {
+                capacity.blocks(geometry.offset_bits()).unwrap_or_else(|e| {
+                    eprintln!("Invalid capacity: {}", e);
+                    std::process::exit(1);
+                })
+            });
+            let outputs = |resumed| run_outputs(lease, Some(recording), capacity, resumed);
+            let settings = RunSettings {
+                mode: mode.to_string(),
+                trace: trace_path.to_string(),
+                geometry,
+                mapping,
+                seed: lease.seed,
+            };
+            let (result, outputs) = if let Some(threads) = *threads {
+                let result =
+                    run_trace_parallel(test_trace, table, &geometry, &mapping, None, &rng, threads);
+                (result, RunOutputs::default())
+            } else if geometry.sets == 1 {
+                let (test_cache, mut outputs) = restore(
+                    recording,
+                    &settings,
+                    &mut test_trace,
+                    &mut rng,
+                    outputs,
+                    || FullyAssociativeCache::new(None),
+                );
+                let result =
+                    run_trace_virtual(test_cache, test_trace, table, &mapping, &rng, &mut outputs);
+                (result, outputs)
+            } else {
+                let (test_cache, mut outputs) = restore(
+                    recording,
+                    &settings,
+                    &mut test_trace,
+                    &mut rng,
+                    outputs,
+                    || VirtualCache::new(&geometry),
+                );
+                let result =
+                    run_trace_virtual(test_cache, test_trace, table, &mapping, &rng, &mut outputs);
+                (result, outputs)
+            };
+            write_ref_report(&outputs, lease, table, labels.as_ref());
+            result
         }
-        2 => {
-            run_trace_virtual_predict(test_trace, &test_table);
+        Command::Predict { offset, lease } => {
+            let table = test_table.as_ref().unwrap();
+            let mut outputs = run_outputs(lease, None, None, None);
+            let result = run_trace_virtual_predict(test_trace, table, *offset, &rng, &mut outputs);
+            write_ref_report(&outputs, lease, table, labels.as_ref());
+            result
         }
-        3 => {
-            run_lru_simulation(
-                test_trace,
-                cache_size as usize,
-                num_sets as usize,
-                associativity as usize,
-                offset,
-                set,
-            );
+        Command::Lru { .. } => {
+            run_lru_simulation(test_trace, &geometry.unwrap(), &mapping.unwrap())
         }
-        _ => {
-            eprintln!("Invalid mode specified");
+        Command::Aet { model } | Command::Footprint { model } => {
+            let lru_model = match &cli.command {
+                Command::Aet { .. } => LruModel::Aet,
+                _ => LruModel::Footprint,
+            };
+            let profile = ReuseProfile::new(test_trace, geometry.unwrap().offset_bits());
+            if let Some(path) = &model.mrc {
+                lru_model
+                    .write_miss_ratio_curve(&profile, path)
+                    .expect("Error writing miss ratio curve");
+            }
+            println!("Blocks: {}", profile.blocks());
+            run_lru_model(lru_model, &profile, &geometry.unwrap())
         }
-    }
+        Command::LeaseModel { offset } => {
+            let table = test_table.as_ref().unwrap();
+            run_lease_model(test_trace, table, *offset).unwrap_or_else(|e| {
+                eprintln!("Cannot predict leases: {}", e);
+                std::process::exit(1);
+            })
+        }
+        Command::Batch { .. }
+        | Command::Diff { .. }
+        | Command::StackDistance { .. }
+        | Command::ConvertLeases { .. } => unreachable!("handled above"),
+    };
+
+    result.print(mode);
 
     let duration = start.elapsed(); // End timing
 
     println!("Time elapsed is: {:?}", duration);
+
+    if let Some(output) = &cli.output {
+        let row = ResultRow {
+            benchmark: None,
+            mode,
+            trace: trace_path,
+            lease_table: uses_leases.then_some(lease_table_path.as_str()),
+            geometry,
+            index: mapping.map(|mapping| mapping.function),
+            seed,
+            result,
+        };
+        append_rows(output, &ResultRow::HEADER, &[row.fields()])
+            .expect("Error writing results file");
+    }
 }
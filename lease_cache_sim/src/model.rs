@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::io;
+
+use crate::cache::Writebacks;
+use crate::geometry::CacheGeometry;
+use crate::lease_table::Trace;
+use crate::leases::LeaseTable;
+use crate::report::{write_rows, SimResult};
+
+/// A multiset of times, which answers how many of them exceed a time and by how much in
+/// O(log K) for K distinct times.
+struct Tail {
+    /// the distinct times, in increasing order
+    times: Vec<u64>,
+    /// the number of times from each distinct time on
+    counts: Vec<u64>,
+    /// the sum of the times from each distinct time on
+    sums: Vec<f64>,
+}
+
+impl Tail {
+    fn new(histogram: HashMap<u64, u64>) -> Tail {
+        let mut histogram: Vec<(u64, u64)> = histogram.into_iter().collect();
+        histogram.sort_unstable();
+        let mut counts = vec![0; histogram.len() + 1];
+        let mut sums = vec![0.0; histogram.len() + 1];
+        for (i, &(time, count)) in histogram.iter().enumerate().rev() {
+            counts[i] = counts[i + 1] + count;
+            sums[i] = sums[i + 1] + (time * count) as f64;
+        }
+        Tail {
+            times: histogram.into_iter().map(|(time, _)| time).collect(),
+            counts,
+            sums,
+        }
+    }
+
+    /// The number of times greater than `w`.
+    fn count_above(&self, w: u64) -> u64 {
+        self.counts[self.times.partition_point(|&time| time <= w)]
+    }
+
+    /// The total of `time - w` over the times greater than `w`.
+    fn excess_above(&self, w: u64) -> f64 {
+        let i = self.times.partition_point(|&time| time <= w);
+        self.sums[i] - (w * self.counts[i]) as f64
+    }
+
+    fn total(&self) -> f64 {
+        self.sums[0]
+    }
+
+    fn max(&self) -> u64 {
+        self.times.last().copied().unwrap_or(0)
+    }
+}
+
+/// The locality of a trace, from which the miss ratios of a fully associative LRU cache are
+/// predicted without simulating it.
+///
+/// Times are counted in accesses: the reuse interval of an access is the number of accesses
+/// since the previous access to its block.
+pub struct ReuseProfile {
+    accesses: u64,
+    /// the number of distinct blocks, whose first accesses are cold misses
+    blocks: u64,
+    reuse_intervals: Tail,
+    /// the time of the first access to every block, from 1
+    first_accesses: Tail,
+    /// the time from the last access to every block to the end of the trace, from 1
+    last_accesses: Tail,
+}
+
+impl ReuseProfile {
+    /// The profile of the blocks of `1 << offset` bytes accessed by `trace`.
+    pub fn new(trace: Trace, offset: u64) -> ReuseProfile {
+        let mut last_access: HashMap<u64, u64> = HashMap::new();
+        let mut reuse_intervals: HashMap<u64, u64> = HashMap::new();
+        let mut first_accesses: HashMap<u64, u64> = HashMap::new();
+        let mut time = 0;
+        for item in trace {
+            time += 1;
+            match last_access.insert(item.access_tag >> offset, time) {
+                Some(last) => *reuse_intervals.entry(time - last).or_default() += 1,
+                None => *first_accesses.entry(time).or_default() += 1,
+            }
+        }
+        let mut last_accesses: HashMap<u64, u64> = HashMap::new();
+        for last in last_access.values() {
+            *last_accesses.entry(time + 1 - last).or_default() += 1;
+        }
+        ReuseProfile {
+            accesses: time,
+            blocks: last_access.len() as u64,
+            reuse_intervals: Tail::new(reuse_intervals),
+            first_accesses: Tail::new(first_accesses),
+            last_accesses: Tail::new(last_accesses),
+        }
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.accesses
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// The probability that the next access to a block comes more than `t` accesses after the
+    /// current one, counting blocks never accessed again as reused at infinity.
+    fn reuse_beyond(&self, t: u64) -> f64 {
+        (self.reuse_intervals.count_above(t) + self.blocks) as f64 / self.accesses as f64
+    }
+
+    /// The expected number of blocks accessed in the last `t` accesses that have not been
+    /// reused since, i.e. the size of an LRU cache whose blocks are evicted `t` accesses after
+    /// their last access.
+    fn aet_cache_size(&self, t: u64) -> f64 {
+        // the sum of reuse_beyond(0..t)
+        let below = self.reuse_intervals.total() - self.reuse_intervals.excess_above(t);
+        (below + (self.blocks * t) as f64) / self.accesses as f64
+    }
+
+    /// The LRU miss ratio of a cache of `size` blocks by the average eviction time (AET) model:
+    /// the eviction time is the time `t` at which the blocks not yet reused fill the cache, and
+    /// an access misses if its reuse interval is longer.
+    pub fn aet_miss_ratio(&self, size: u64) -> f64 {
+        if self.accesses == 0 {
+            return 0.0;
+        }
+        let size = size as f64;
+        let max = self.reuse_intervals.max();
+        if self.aet_cache_size(max) < size {
+            return self.reuse_beyond(max);
+        }
+        // the first time at which the cache is full
+        let (mut low, mut high) = (0, max);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.aet_cache_size(mid) < size {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        self.reuse_beyond(low)
+    }
+
+    /// The average footprint of the windows of `w` accesses: the number of distinct blocks
+    /// they access.
+    pub fn footprint(&self, w: u64) -> f64 {
+        let n = self.accesses;
+        if w == 0 {
+            return 0.0;
+        }
+        let w = w.min(n);
+        let excess = self.first_accesses.excess_above(w)
+            + self.last_accesses.excess_above(w)
+            + self.reuse_intervals.excess_above(w);
+        self.blocks as f64 - excess / (n - w + 1) as f64
+    }
+
+    /// The LRU miss ratio of a cache of `size` blocks by the footprint model: the growth of the
+    /// footprint at the window length whose footprint fills the cache.
+    pub fn footprint_miss_ratio(&self, size: u64) -> f64 {
+        if self.accesses < 2 {
+            return self.blocks as f64 / self.accesses.max(1) as f64;
+        }
+        // the longest window whose footprint fits, from 1 to n - 1
+        let size = size as f64;
+        let (mut low, mut high) = (1, self.accesses - 1);
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if self.footprint(mid) <= size {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        (self.footprint(low + 1) - self.footprint(low)).max(0.0)
+    }
+}
+
+/// A model of the miss ratio of a fully associative LRU cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LruModel {
+    Aet,
+    Footprint,
+}
+
+impl LruModel {
+    pub fn miss_ratio(self, profile: &ReuseProfile, size: u64) -> f64 {
+        match self {
+            LruModel::Aet => profile.aet_miss_ratio(size),
+            LruModel::Footprint => profile.footprint_miss_ratio(size),
+        }
+    }
+
+    /// Write the miss ratio of every cache size from 1 block to the number of blocks in the
+    /// trace, beyond which the models predict only cold misses.
+    pub fn write_miss_ratio_curve(self, profile: &ReuseProfile, file_path: &str) -> io::Result<()> {
+        let rows: Vec<Vec<String>> = (1..=profile.blocks())
+            .map(|size| vec![size.to_string(), self.miss_ratio(profile, size).to_string()])
+            .collect();
+        write_rows(file_path, &["cache_size", "miss_ratio"], &rows)
+    }
+}
+
+/// The expected behavior of a virtual lease cache under a lease table.
+pub struct LeasePrediction {
+    pub accesses: u64,
+    pub miss_ratio: f64,
+    /// the average number of blocks with a live lease
+    pub occupancy: f64,
+}
+
+/// Predict the misses and occupancy of a virtual lease cache with blocks of `1 << offset`
+/// bytes over `trace` from the lease distribution of every reference, without drawing leases.
+///
+/// A block is cached from an access until its lease runs out or it is accessed again, so an
+/// access with lease `L` and reuse interval `ri` occupies the cache for `min(L, ri)` accesses,
+/// and the next access hits if `ri < L`, as in `predict`. The last access to a block occupies
+/// the cache until the lease runs out or the trace ends. Each reference gives its short lease
+/// with its short lease probability and its long lease otherwise.
+pub fn predict_leases(
+    trace: Trace,
+    table: &LeaseTable,
+    offset: u64,
+) -> io::Result<LeasePrediction> {
+    let lease_of = |reference: u64| {
+        table.query(&reference).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no lease for reference {:x}", reference),
+            )
+        })
+    };
+    // the expected occupancy and miss probability of an access by a reference that is next
+    // accessed `interval` accesses later
+    let expect = |(short, long, short_prob): (u64, u64, f64), interval: u64| {
+        let occupancy = short_prob * short.min(interval) as f64
+            + (1.0 - short_prob) * long.min(interval) as f64;
+        let miss = short_prob * (interval >= short) as u64 as f64
+            + (1.0 - short_prob) * (interval >= long) as u64 as f64;
+        (occupancy, miss)
+    };
+
+    // the time and reference of the last access to every block
+    let mut last_access: HashMap<u64, (u64, u64)> = HashMap::new();
+    let mut misses = 0.0;
+    let mut occupancy = 0.0;
+    let mut time = 0;
+    for item in trace {
+        if let Some((last, reference)) =
+            last_access.insert(item.access_tag >> offset, (time, item.reference))
+        {
+            let (occupied, miss) = expect(lease_of(reference)?, time - last);
+            occupancy += occupied;
+            misses += miss;
+        } else {
+            misses += 1.0;
+        }
+        time += 1;
+    }
+    for &(last, reference) in last_access.values() {
+        occupancy += expect(lease_of(reference)?, time - last).0;
+    }
+
+    let accesses = time.max(1) as f64;
+    Ok(LeasePrediction {
+        accesses: time,
+        miss_ratio: misses / accesses,
+        occupancy: occupancy / accesses,
+    })
+}
+
+/// Predict the miss ratio of a fully associative LRU cache with the capacity of `geometry`.
+pub fn run_lru_model(
+    model: LruModel,
+    profile: &ReuseProfile,
+    geometry: &CacheGeometry,
+) -> SimResult {
+    let miss_ratio = model.miss_ratio(profile, geometry.capacity_blocks());
+    SimResult {
+        accesses: profile.accesses(),
+        miss_ratio,
+        forced_evictions: 0,
+        writebacks: Writebacks::default(),
+        occupancy: None,
+    }
+}
+
+pub fn run_lease_model(trace: Trace, table: &LeaseTable, offset: u64) -> io::Result<SimResult> {
+    let prediction = predict_leases(trace, table, offset)?;
+    Ok(SimResult {
+        accesses: prediction.accesses,
+        miss_ratio: prediction.miss_ratio,
+        forced_evictions: 0,
+        writebacks: Writebacks::default(),
+        occupancy: Some(prediction.occupancy),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::LeaseCache;
+    use crate::geometry::CacheGeometry;
+    use crate::index::{AddressMapping, IndexFunction};
+    use crate::lease_table::{access_rng, init_cache_block, test_trace, SimRng};
+    use crate::leases::Lease;
+    use crate::virtual_cache::VirtualCache;
+    use rand::SeedableRng;
+
+    /// With every reference always giving the same lease, the model is exact: its misses and
+    /// occupancy are those of a virtual cache simulated with the same block size.
+    #[test]
+    fn lease_model_matches_virtual_cache_with_deterministic_leases() {
+        let offset = 5;
+        let (path, _) = test_trace("lease_model", 5000, 4);
+        let leases = (0..4)
+            .map(|reference| Lease {
+                phase: 0,
+                reference,
+                short_lease: 3 + 7 * reference,
+                long_lease: 120,
+                short_prob: (reference % 2) as f64,
+            })
+            .collect();
+        let table = LeaseTable::from_leases(Vec::new(), leases);
+        let prediction = predict_leases(Trace::new(&path).unwrap(), &table, offset).unwrap();
+
+        let geometry = CacheGeometry::from_params(None, Some(1), offset, Some(2)).unwrap();
+        let mapping = AddressMapping::new(IndexFunction::Modulo, &geometry, None).unwrap();
+        let rng = SimRng::seed_from_u64(0);
+        let mut cache = VirtualCache::new(&geometry);
+        // the number of live blocks after every access, summed
+        let mut occupancy = 0;
+        for item in Trace::new(&path).unwrap() {
+            let mut access_rng = access_rng(&rng, cache.step());
+            let block = init_cache_block(&item, &mapping, &table, &mut access_rng).unwrap();
+            cache.update(block, &mut access_rng);
+            occupancy += cache.snapshot().occupancy.iter().sum::<usize>();
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(prediction.accesses, 5000);
+        assert_eq!(prediction.miss_ratio, cache.calculate_miss_ratio());
+        assert_eq!(prediction.occupancy, occupancy as f64 / 5000.0);
+    }
+}
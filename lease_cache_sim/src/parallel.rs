@@ -0,0 +1,197 @@
+use rayon::prelude::*;
+
+use crate::cache::{CacheSet, Writebacks};
+use crate::geometry::CacheGeometry;
+use crate::index::AddressMapping;
+use crate::lease_table::{access_rng, init_cache_block, SimRng, Trace, TraceItem};
+use crate::leases::LeaseTable;
+use crate::report::SimResult;
+
+/// The number of trace records read and distributed to the set groups at a time.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// A slice of the cache's sets, simulated on one thread.
+///
+/// A set is only aged when it is accessed: the leases of its blocks are counted down by the
+/// number of accesses to any set since its last access, which is what the sequential cache does
+/// one step at a time. Together with the per-access random streams of `access_rng`, this makes
+/// every access come out exactly as in the sequential simulation.
+struct SetGroup {
+    /// the sets `s` with `s % groups == index`, at position `s / groups`
+    sets: Vec<CacheSet>,
+    /// the step of the last access to each set
+    last_access: Vec<Option<u64>>,
+    /// the accesses of the current chunk that map to this group, with their steps
+    pending: Vec<(u64, TraceItem)>,
+    misses: u64,
+    forced_evictions: u64,
+}
+
+impl SetGroup {
+    fn run(&mut self, groups: usize, table: &LeaseTable, mapping: &AddressMapping, rng: &SimRng) {
+        for (step, trace_item) in self.pending.drain(..) {
+            let mut access_rng = access_rng(rng, step);
+            let block = init_cache_block(&trace_item, mapping, table, &mut access_rng)
+                .expect("Error in packing cache block");
+            let slot = block.set_index as usize / groups;
+
+            if let Some(last) = self.last_access[slot] {
+                self.sets[slot].age(step - last);
+            }
+            self.last_access[slot] = Some(step);
+
+            let result = self.sets[slot].access(block, &mut access_rng);
+            self.misses += !result.hit as u64;
+            self.forced_evictions += result.forced_evictions;
+        }
+    }
+
+    /// Age every set to the last of `accesses` steps, so that leases running out after a set's
+    /// last access are counted as in the sequential simulation.
+    fn finish(&mut self, accesses: u64) {
+        for (set, last) in self.sets.iter_mut().zip(&self.last_access) {
+            if let Some(last) = last {
+                set.age(accesses - 1 - last);
+            }
+        }
+    }
+}
+
+/// Simulate a set-associative lease cache with the sets spread over `threads` threads.
+///
+/// The trace is read in chunks, and each access is handed to the group holding its set. Groups
+/// share nothing but the trace, so they run in parallel, and their statistics are added up at
+/// the end. Each set holds at most `ways` blocks, or any number if `ways` is `None`, which
+/// simulates the virtual cache. The results are identical to `run_trace` and
+/// `run_trace_virtual` with the same seed.
+///
+/// Blocks in a skewed-associative cache can move between sets, so it cannot be partitioned and
+/// is not supported here.
+pub fn run_trace_parallel(
+    trace: Trace,
+    table: &LeaseTable,
+    geometry: &CacheGeometry,
+    mapping: &AddressMapping,
+    ways: Option<u64>,
+    rng: &SimRng,
+    threads: usize,
+) -> SimResult {
+    let groups = threads.clamp(1, geometry.sets as usize);
+    let mut set_groups: Vec<SetGroup> = (0..groups)
+        .map(|index| {
+            let num_sets = (geometry.sets as usize)
+                .saturating_sub(index)
+                .div_ceil(groups);
+            SetGroup {
+                sets: (0..num_sets)
+                    .map(|_| CacheSet::new(ways.unwrap_or(u64::MAX)))
+                    .collect(),
+                last_access: vec![None; num_sets],
+                pending: Vec::new(),
+                misses: 0,
+                forced_evictions: 0,
+            }
+        })
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Error creating thread pool");
+
+    let mut trace = trace.peekable();
+    let mut step: u64 = 0;
+    while trace.peek().is_some() {
+        for trace_item in trace.by_ref().take(CHUNK_SIZE) {
+            let set_index = mapping.set_index(trace_item.access_tag, 0) as usize;
+            set_groups[set_index % groups]
+                .pending
+                .push((step, trace_item));
+            step += 1;
+        }
+        pool.install(|| {
+            set_groups
+                .par_iter_mut()
+                .for_each(|group| group.run(groups, table, mapping, rng));
+        });
+    }
+
+    set_groups.iter_mut().for_each(|group| group.finish(step));
+
+    let misses: u64 = set_groups.iter().map(|group| group.misses).sum();
+    let forced_evictions: u64 = set_groups.iter().map(|group| group.forced_evictions).sum();
+    let mut writebacks = Writebacks::default();
+    for set in set_groups.iter().flat_map(|group| &group.sets) {
+        writebacks.add(&set.writebacks());
+    }
+
+    SimResult {
+        accesses: step,
+        miss_ratio: misses as f64 / step as f64,
+        forced_evictions,
+        writebacks,
+        occupancy: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Cache;
+    use crate::geometry::Capacity;
+    use crate::index::IndexFunction;
+    use crate::lease_table::{run_trace, run_trace_virtual, test_trace, RunOutputs};
+    use crate::virtual_cache::VirtualCache;
+    use rand::SeedableRng;
+
+    fn cache(ways: u64, index: IndexFunction) -> (CacheGeometry, AddressMapping) {
+        let geometry =
+            CacheGeometry::from_params(Some(Capacity::Blocks(16 * ways)), Some(ways), 3, None)
+                .unwrap();
+        let mapping = AddressMapping::new(index, &geometry, None).unwrap();
+        (geometry, mapping)
+    }
+
+    /// Simulate the trace on one thread and on three, with the same results.
+    fn check_parallel(name: &str, ways: Option<u64>, index: IndexFunction) {
+        let (path, table) = test_trace(name, 5000, 2);
+        let (geometry, mapping) = cache(ways.unwrap_or(1), index);
+        let rng = SimRng::seed_from_u64(7);
+        let mut outputs = RunOutputs::default();
+        let trace = Trace::new(&path).unwrap();
+        let sequential = match ways {
+            Some(_) => {
+                let cache = Cache::new(&geometry, &mapping);
+                run_trace(cache, trace, &table, &mapping, &rng, &mut outputs)
+            }
+            None => {
+                let cache = VirtualCache::new(&geometry);
+                run_trace_virtual(cache, trace, &table, &mapping, &rng, &mut outputs)
+            }
+        };
+        let trace = Trace::new(&path).unwrap();
+        let parallel = run_trace_parallel(trace, &table, &geometry, &mapping, ways, &rng, 3);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(parallel, sequential);
+        assert_eq!(parallel.accesses, 5000);
+        if ways.is_some() {
+            assert!(parallel.forced_evictions > 0);
+        }
+    }
+
+    #[test]
+    fn physical_modulo_matches_sequential_run() {
+        check_parallel("parallel_modulo", Some(4), IndexFunction::Modulo);
+    }
+
+    #[test]
+    fn physical_xor_fold_matches_sequential_run() {
+        check_parallel("parallel_xor_fold", Some(4), IndexFunction::XorFold);
+    }
+
+    #[test]
+    fn virtual_matches_sequential_run() {
+        check_parallel("parallel_virtual", None, IndexFunction::Modulo);
+    }
+}
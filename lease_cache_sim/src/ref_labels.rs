@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+
+use csv::ReaderBuilder;
+
+/// The source location of every reference, read from the reference metadata written by
+/// `trace_gen` (`reference,array,subscripts,depth,loops`), so that reports can name references
+/// as written, e.g. `A[i][k]`, instead of by ID.
+pub struct RefLabels {
+    labels: HashMap<u64, String>,
+}
+
+impl RefLabels {
+    pub fn load(file_path: &str) -> io::Result<RefLabels> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut reader = ReaderBuilder::new().from_reader(File::open(file_path)?);
+        let mut labels = HashMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| invalid(e.to_string()))?;
+            let (Some(reference), Some(array), Some(subscripts)) =
+                (record.get(0), record.get(1), record.get(2))
+            else {
+                return Err(invalid(format!(
+                    "incomplete reference record: {:?}",
+                    record
+                )));
+            };
+            let reference = u64::from_str_radix(reference.trim(), 16)
+                .map_err(|_| invalid(format!("invalid reference ID '{}'", reference)))?;
+            labels.insert(reference, format!("{}{}", array.trim(), subscripts.trim()));
+        }
+        Ok(RefLabels { labels })
+    }
+
+    /// The label of `reference`, or an empty string if the metadata does not have it.
+    pub fn label(&self, reference: u64) -> &str {
+        self.labels.get(&reference).map_or("", String::as_str)
+    }
+}
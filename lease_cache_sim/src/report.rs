@@ -0,0 +1,189 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::Writebacks;
+use crate::geometry::CacheGeometry;
+use crate::index::IndexFunction;
+use crate::leases::LeaseTable;
+use crate::ref_labels::RefLabels;
+
+/// The summary of one simulation run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimResult {
+    pub accesses: u64,
+    pub miss_ratio: f64,
+    pub forced_evictions: u64,
+    pub writebacks: Writebacks,
+    /// the average number of cached blocks, for the modes that estimate it
+    pub occupancy: Option<f64>,
+}
+
+impl SimResult {
+    /// Print the result of a run of `mode`. Dirty blocks still in the cache when the trace ends
+    /// are not counted as writebacks.
+    pub fn print(&self, mode: &str) {
+        println!("Miss ratio: {}", self.miss_ratio);
+        if mode == "physical" {
+            println!(
+                "Force Eviction: {} / {} ({})",
+                self.forced_evictions,
+                self.accesses,
+                self.forced_evictions as f64 / self.accesses as f64
+            );
+        }
+        if let Some(occupancy) = self.occupancy {
+            println!("Occupancy: {}", occupancy);
+        }
+        if matches!(mode, "physical" | "virtual" | "lru") {
+            println!(
+                "Writebacks: {} on lease expiry, {} on forced eviction",
+                self.writebacks.expiry, self.writebacks.eviction
+            );
+        }
+    }
+}
+
+/// One row of a results table: a run together with the parameters it was run with. Parameters
+/// a mode does not use are left empty.
+pub struct ResultRow<'a> {
+    pub benchmark: Option<&'a str>,
+    pub mode: &'a str,
+    pub trace: &'a str,
+    pub lease_table: Option<&'a str>,
+    pub geometry: Option<CacheGeometry>,
+    pub index: Option<IndexFunction>,
+    pub seed: Option<u64>,
+    pub result: SimResult,
+}
+
+impl ResultRow<'_> {
+    pub const HEADER: [&'static str; 15] = [
+        "benchmark",
+        "mode",
+        "trace",
+        "lease_table",
+        "sets",
+        "ways",
+        "block_size",
+        "index",
+        "seed",
+        "accesses",
+        "miss_ratio",
+        "forced_evictions",
+        "expiry_writebacks",
+        "eviction_writebacks",
+        "occupancy",
+    ];
+
+    pub fn fields(&self) -> Vec<String> {
+        fn optional<T: ToString>(value: Option<T>) -> String {
+            value.map_or(String::new(), |v| v.to_string())
+        }
+
+        vec![
+            optional(self.benchmark),
+            self.mode.to_string(),
+            self.trace.to_string(),
+            optional(self.lease_table),
+            optional(self.geometry.map(|g| g.sets)),
+            optional(self.geometry.map(|g| g.ways)),
+            optional(self.geometry.map(|g| g.block_size)),
+            optional(self.index.map(|i| format!("{:?}", i))),
+            optional(self.seed),
+            self.result.accesses.to_string(),
+            self.result.miss_ratio.to_string(),
+            self.result.forced_evictions.to_string(),
+            self.result.writebacks.expiry.to_string(),
+            self.result.writebacks.eviction.to_string(),
+            optional(self.result.occupancy),
+        ]
+    }
+}
+
+/// The accesses and misses of every reference in one run.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RefReport {
+    /// accesses and misses, by reference
+    references: BTreeMap<u64, (u64, u64)>,
+}
+
+impl RefReport {
+    pub const HEADER: [&'static str; 8] = [
+        "reference",
+        "label",
+        "accesses",
+        "misses",
+        "miss_ratio",
+        "short_lease",
+        "long_lease",
+        "short_prob",
+    ];
+
+    pub fn record(&mut self, reference: u64, hit: bool) {
+        let (accesses, misses) = self.references.entry(reference).or_default();
+        *accesses += 1;
+        *misses += !hit as u64;
+    }
+
+    /// Write one row per reference, with its leases in `table` and its label in `labels`, the
+    /// references with the most misses first.
+    pub fn write(
+        &self,
+        file_path: &str,
+        table: &LeaseTable,
+        labels: Option<&RefLabels>,
+    ) -> io::Result<()> {
+        let mut references: Vec<_> = self.references.iter().collect();
+        references.sort_by_key(|(_, (_, misses))| std::cmp::Reverse(*misses));
+        let rows: Vec<Vec<String>> = references
+            .into_iter()
+            .map(|(&reference, &(accesses, misses))| {
+                let (short_lease, long_lease, short_prob) = table
+                    .query(&reference)
+                    .expect("Error in query lease for the access");
+                vec![
+                    format!("{:x}", reference),
+                    labels.map_or(String::new(), |labels| labels.label(reference).to_string()),
+                    accesses.to_string(),
+                    misses.to_string(),
+                    (misses as f64 / accesses as f64).to_string(),
+                    format!("{:x}", short_lease),
+                    format!("{:x}", long_lease),
+                    short_prob.to_string(),
+                ]
+            })
+            .collect();
+        write_rows(file_path, &RefReport::HEADER, &rows)
+    }
+}
+
+/// Append rows to a CSV results file, writing the header first if the file is new or empty.
+pub fn append_rows(file_path: &str, header: &[&str], rows: &[Vec<String>]) -> io::Result<()> {
+    let is_new = !Path::new(file_path).exists() || std::fs::metadata(file_path)?.len() == 0;
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(file_path)?;
+    write_table(file, is_new.then_some(header), rows)
+}
+
+/// Write a complete results table, replacing the file if it exists.
+pub fn write_rows(file_path: &str, header: &[&str], rows: &[Vec<String>]) -> io::Result<()> {
+    write_table(File::create(file_path)?, Some(header), rows)
+}
+
+fn write_table(file: File, header: Option<&[&str]>, rows: &[Vec<String>]) -> io::Result<()> {
+    let mut writer = csv::Writer::from_writer(file);
+
+    if let Some(header) = header {
+        writer.write_record(header)?;
+    }
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::checkpoint::reopen_at_checkpoint;
+
+/// The state of a cache at one point in time.
+pub struct Snapshot {
+    pub step: u64,
+    /// number of blocks currently held in each set
+    pub occupancy: Vec<usize>,
+    pub miss_ratio: f64,
+    pub forced_evictions: u64,
+}
+
+impl Snapshot {
+    pub fn cache_size(&self) -> usize {
+        self.occupancy.iter().sum()
+    }
+}
+
+/// Writes a snapshot every `interval` accesses into a time-series CSV.
+///
+/// Columns are `step,cache_size,capacity,miss_ratio,forced_evictions` followed by one
+/// `set_<i>` column per set. `cache_size` is the number of blocks with a live lease (the virtual
+/// cache size in virtual mode), `capacity` is the physical cache size it is compared against,
+/// left empty when there is none.
+pub struct SnapshotWriter {
+    writer: BufWriter<File>,
+    interval: u64,
+    capacity: Option<u64>,
+    header_written: bool,
+}
+
+impl SnapshotWriter {
+    pub fn create(
+        file_path: &str,
+        interval: u64,
+        capacity: Option<u64>,
+    ) -> io::Result<SnapshotWriter> {
+        let file = File::create(file_path)?;
+        Ok(SnapshotWriter {
+            writer: BufWriter::new(file),
+            interval,
+            capacity,
+            header_written: false,
+        })
+    }
+
+    /// Continue the snapshots of a simulation resumed from a checkpoint taken when the file was
+    /// `len` bytes long, keeping the header and the snapshots up to the checkpoint and dropping
+    /// any taken after it.
+    pub fn resume(
+        file_path: &str,
+        interval: u64,
+        capacity: Option<u64>,
+        len: u64,
+    ) -> io::Result<SnapshotWriter> {
+        let file = reopen_at_checkpoint(file_path, len)?;
+        Ok(SnapshotWriter {
+            writer: BufWriter::new(file),
+            interval,
+            capacity,
+            header_written: len > 0,
+        })
+    }
+
+    /// whether a snapshot is due after `step` accesses
+    pub fn is_due(&self, step: u64) -> bool {
+        step.is_multiple_of(self.interval)
+    }
+
+    pub fn record(&mut self, snapshot: &Snapshot) -> io::Result<()> {
+        if !self.header_written {
+            write!(
+                self.writer,
+                "step,cache_size,capacity,miss_ratio,forced_evictions"
+            )?;
+            for index in 0..snapshot.occupancy.len() {
+                write!(self.writer, ",set_{}", index)?;
+            }
+            writeln!(self.writer)?;
+            self.header_written = true;
+        }
+
+        write!(
+            self.writer,
+            "{},{},{},{},{}",
+            snapshot.step,
+            snapshot.cache_size(),
+            self.capacity
+                .map_or(String::new(), |capacity| capacity.to_string()),
+            snapshot.miss_ratio,
+            snapshot.forced_evictions
+        )?;
+        for occupancy in &snapshot.occupancy {
+            write!(self.writer, ",{}", occupancy)?;
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// the length of the file, counting only the snapshots flushed to it
+    pub fn flushed_len(&self) -> io::Result<u64> {
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use crate::lease_table::Trace;
+use crate::report::write_rows;
+
+/// The smallest number of time slots the stack keeps, so that short traces are not compacted
+/// over and over.
+const MIN_SLOTS: usize = 1 << 10;
+
+/// Computes the LRU stack distance of every access: the number of distinct blocks accessed
+/// since the previous access to the same block, itself included, so that an access hits in a
+/// fully associative LRU cache of `c` blocks exactly when its stack distance is at most `c`.
+///
+/// Every block is marked at the slot of its last access in a Fenwick tree, and the stack
+/// distance is the number of marks after the previous slot of the block. When the slots run out
+/// they are renumbered in the order of the marks, so the tree holds at most twice as many
+/// slots as there are blocks and an access takes O(log M) time for M blocks.
+pub struct StackDistance {
+    /// the slot of the last access to every block
+    last_slot: HashMap<u64, usize>,
+    /// the Fenwick tree over the slots, counting the blocks whose last access is in each
+    tree: Vec<u64>,
+    /// the slot of the next access
+    next_slot: usize,
+}
+
+impl Default for StackDistance {
+    fn default() -> Self {
+        StackDistance::new()
+    }
+}
+
+impl StackDistance {
+    pub fn new() -> StackDistance {
+        StackDistance {
+            last_slot: HashMap::new(),
+            tree: vec![0; MIN_SLOTS + 1],
+            next_slot: 0,
+        }
+    }
+
+    /// The stack distance of an access to `block`, or `None` if the block was never accessed.
+    pub fn access(&mut self, block: u64) -> Option<u64> {
+        if self.next_slot + 1 == self.tree.len() {
+            self.compact();
+        }
+        let distance = self.last_slot.get(&block).copied().map(|slot| {
+            let later = self.prefix(self.next_slot) - self.prefix(slot + 1);
+            self.add(slot, -1);
+            later + 1
+        });
+        self.add(self.next_slot, 1);
+        self.last_slot.insert(block, self.next_slot);
+        self.next_slot += 1;
+        distance
+    }
+
+    /// The number of distinct blocks accessed so far.
+    pub fn blocks(&self) -> usize {
+        self.last_slot.len()
+    }
+
+    /// The number of marks in the slots before `slot`.
+    fn prefix(&self, slot: usize) -> u64 {
+        let mut count = 0;
+        let mut i = slot;
+        while i > 0 {
+            count += self.tree[i];
+            i &= i - 1;
+        }
+        count
+    }
+
+    fn add(&mut self, slot: usize, delta: i64) {
+        let mut i = slot + 1;
+        while i < self.tree.len() {
+            self.tree[i] = self.tree[i].wrapping_add_signed(delta);
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// Give the blocks the first slots, in the order of their last accesses, and make room for
+    /// as many accesses again.
+    fn compact(&mut self) {
+        let mut blocks: Vec<(usize, u64)> = self
+            .last_slot
+            .iter()
+            .map(|(&block, &slot)| (slot, block))
+            .collect();
+        blocks.sort_unstable();
+        let slots = (2 * blocks.len()).max(MIN_SLOTS);
+        self.tree = vec![0; slots + 1];
+        for (slot, &(_, block)) in blocks.iter().enumerate() {
+            self.last_slot.insert(block, slot);
+            self.tree[slot + 1] = 1;
+        }
+        // build the tree in place from the marks
+        for i in 1..self.tree.len() {
+            let parent = i + (i & i.wrapping_neg());
+            if parent < self.tree.len() {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+        self.next_slot = blocks.len();
+    }
+}
+
+/// A histogram of stack distances, with the accesses to blocks never accessed before counted
+/// apart.
+#[derive(Debug, Clone, Default)]
+pub struct DistanceHistogram {
+    pub distances: BTreeMap<u64, u64>,
+    pub cold: u64,
+}
+
+impl DistanceHistogram {
+    pub fn record(&mut self, distance: Option<u64>) {
+        match distance {
+            Some(distance) => *self.distances.entry(distance).or_default() += 1,
+            None => self.cold += 1,
+        }
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.cold + self.distances.values().sum::<u64>()
+    }
+
+    /// The misses of a fully associative LRU cache at every size where they change, from 0
+    /// blocks up to the largest stack distance, after which only the cold misses remain.
+    pub fn miss_ratio_curve(&self) -> Vec<(u64, u64)> {
+        let mut misses = self.accesses();
+        let mut curve = vec![(0, misses)];
+        for (&distance, &count) in &self.distances {
+            misses -= count;
+            curve.push((distance, misses));
+        }
+        curve
+    }
+
+    /// One `distance,count` row per distance, the cold accesses first with an empty distance.
+    fn rows(&self) -> Vec<Vec<String>> {
+        let cold = (self.cold > 0).then(|| (String::new(), self.cold));
+        cold.into_iter()
+            .chain(
+                self.distances
+                    .iter()
+                    .map(|(distance, &count)| (distance.to_string(), count)),
+            )
+            .map(|(distance, count)| vec![distance, count.to_string()])
+            .collect()
+    }
+}
+
+/// The stack distance histograms of a trace, of all accesses and of every reference.
+#[derive(Default)]
+pub struct StackDistanceHistograms {
+    /// the number of distinct blocks in the trace
+    pub blocks: usize,
+    pub global: DistanceHistogram,
+    pub references: BTreeMap<u64, DistanceHistogram>,
+}
+
+impl StackDistanceHistograms {
+    pub fn write_histogram(&self, file_path: &str) -> io::Result<()> {
+        write_rows(file_path, &["distance", "count"], &self.global.rows())
+    }
+
+    pub fn write_ref_histograms(&self, file_path: &str) -> io::Result<()> {
+        let rows: Vec<Vec<String>> = self
+            .references
+            .iter()
+            .flat_map(|(reference, histogram)| {
+                histogram.rows().into_iter().map(move |row| {
+                    let mut fields = vec![format!("{:x}", reference)];
+                    fields.extend(row);
+                    fields
+                })
+            })
+            .collect();
+        write_rows(file_path, &["reference", "distance", "count"], &rows)
+    }
+
+    /// Write the miss ratio curve of a fully associative LRU cache, one row per size where the
+    /// misses change.
+    pub fn write_miss_ratio_curve(&self, file_path: &str) -> io::Result<()> {
+        let accesses = self.global.accesses().max(1) as f64;
+        let rows: Vec<Vec<String>> = self
+            .global
+            .miss_ratio_curve()
+            .into_iter()
+            .map(|(size, misses)| {
+                vec![
+                    size.to_string(),
+                    misses.to_string(),
+                    (misses as f64 / accesses).to_string(),
+                ]
+            })
+            .collect();
+        write_rows(file_path, &["cache_size", "misses", "miss_ratio"], &rows)
+    }
+}
+
+/// Compute the stack distance of every access in `trace` to blocks of `1 << offset` bytes.
+pub fn stack_distances(trace: Trace, offset: u64) -> StackDistanceHistograms {
+    let mut stack = StackDistance::new();
+    let mut histograms = StackDistanceHistograms::default();
+    for item in trace {
+        let distance = stack.access(item.access_tag >> offset);
+        histograms.global.record(distance);
+        histograms
+            .references
+            .entry(item.reference)
+            .or_default()
+            .record(distance);
+    }
+    histograms.blocks = stack.blocks();
+    histograms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lease_table::SimRng;
+    use rand::{Rng, SeedableRng};
+
+    /// The distances match those of an LRU stack kept as a list, across several compactions.
+    #[test]
+    fn matches_naive_lru_stack() {
+        let mut rng = SimRng::seed_from_u64(3);
+        let mut stack = StackDistance::new();
+        // the blocks from the most recently accessed
+        let mut naive: Vec<u64> = Vec::new();
+        for _ in 0..8 * MIN_SLOTS {
+            // mostly a few hot blocks, so that distances are both short and long
+            let block = if rng.gen_bool(0.5) {
+                rng.gen_range(0..16)
+            } else {
+                rng.gen_range(0..700)
+            };
+            let expected = naive.iter().position(|&b| b == block).map(|position| {
+                naive.remove(position);
+                position as u64 + 1
+            });
+            naive.insert(0, block);
+            assert_eq!(stack.access(block), expected);
+        }
+        assert_eq!(stack.blocks(), naive.len());
+    }
+}
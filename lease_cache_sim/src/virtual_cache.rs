@@ -1,50 +1,32 @@
-use crate::cache::CacheBlock;
+use crate::cache::{AccessResult, CacheBlock, LeaseCache, Writebacks};
+use crate::geometry::CacheGeometry;
+use crate::lease_table::SimRng;
+use crate::snapshot::Snapshot;
+use serde::{Deserialize, Serialize};
 use std::io;
 use std::io::Write;
 
+#[derive(Serialize, Deserialize)]
 pub struct VirtualCache {
     sets: Vec<Vec<CacheBlock>>,
     step: u64,
     miss_counter: u64,
+    #[serde(default)]
+    writebacks: Writebacks,
 }
 
 impl VirtualCache {
-    pub fn new(associativity: u64) -> VirtualCache {
-        let sets: Vec<Vec<CacheBlock>> = (0..associativity).map(|_| Vec::new()).collect();
+    /// A virtual cache has the sets of `geometry` but no limit on the number of blocks per set.
+    pub fn new(geometry: &CacheGeometry) -> VirtualCache {
+        let sets: Vec<Vec<CacheBlock>> = (0..geometry.sets).map(|_| Vec::new()).collect();
         VirtualCache {
             sets,
             step: 0,
             miss_counter: 0,
+            writebacks: Writebacks::default(),
         }
     }
 
-    /// update the cache status
-    pub fn update(&mut self, block: CacheBlock) {
-        // update all cache blocks in all the sets
-        self.sets.iter_mut().for_each(|set| {
-            set.retain(|block| block.remaining_lease > 1);
-            set.iter_mut().for_each(|block| {
-                block.tenancy += 1;
-                block.remaining_lease -= 1;
-            });
-        });
-
-        let set_index = block.set_index as usize;
-
-        // check if the block is already in the cache set and update it if it is
-        if let Some(existing_block) = self.sets[set_index].iter_mut().find(|b| b.tag == block.tag) {
-            existing_block.remaining_lease = block.remaining_lease;
-            // println!("HIT {:?}", existing_block);
-        } else {
-            // otherwise, push the block to the cache set
-            // println!("MISS {:?}", block);
-            self.sets[set_index].push(block);
-            self.miss_counter += 1;
-        }
-
-        self.step += 1;
-    }
-
     #[allow(dead_code)]
     pub fn print(&self, output_file: &str) -> io::Result<()> {
         let mut file = std::fs::OpenOptions::new()
@@ -78,8 +60,70 @@ impl VirtualCache {
         writeln!(file)?;
         Ok(())
     }
+}
+
+impl LeaseCache for VirtualCache {
+    /// update the cache status
+    fn update(&mut self, block: CacheBlock, _rng: &mut SimRng) -> AccessResult {
+        // update all cache blocks in all the sets
+        let writebacks = &mut self.writebacks;
+        self.sets.iter_mut().for_each(|set| {
+            set.retain(|block| {
+                let expired = block.remaining_lease <= 1;
+                writebacks.expiry += (expired && block.dirty) as u64;
+                !expired
+            });
+            set.iter_mut().for_each(|block| {
+                block.tenancy += 1;
+                block.remaining_lease -= 1;
+            });
+        });
+
+        let set_index = block.set_index as usize;
+
+        // check if the block is already in the cache set and update it if it is
+        let hit = if let Some(existing_block) =
+            self.sets[set_index].iter_mut().find(|b| b.tag == block.tag)
+        {
+            existing_block.remaining_lease = block.remaining_lease;
+            existing_block.dirty |= block.dirty;
+            true
+        } else {
+            // otherwise, push the block to the cache set
+            self.sets[set_index].push(block);
+            self.miss_counter += 1;
+            false
+        };
+
+        self.step += 1;
+        AccessResult {
+            hit,
+            forced_evictions: 0,
+        }
+    }
+
+    fn step(&self) -> u64 {
+        self.step
+    }
 
-    pub(crate) fn calculate_miss_ratio(&self) -> f64 {
+    fn calculate_miss_ratio(&self) -> f64 {
         self.miss_counter as f64 / self.step as f64
     }
+
+    fn forced_evictions(&self) -> u64 {
+        0
+    }
+
+    fn writebacks(&self) -> Writebacks {
+        self.writebacks
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            step: self.step,
+            occupancy: self.sets.iter().map(|set| set.len()).collect(),
+            miss_ratio: self.calculate_miss_ratio(),
+            forced_evictions: 0,
+        }
+    }
 }
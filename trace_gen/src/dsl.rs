@@ -0,0 +1,774 @@
+use crate::ref_info::RefInfo;
+use crate::ri_utils::DataLayout;
+use crate::tracer::{LoopNest, Position};
+
+/// An integer expression over the parameters and the loop variables in scope, which are
+/// numbered in that order.
+#[derive(Debug, Clone)]
+enum Expr {
+    Const(i64),
+    Var(usize),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+    Mul(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, env: &[i64]) -> i64 {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::Var(index) => env[*index],
+            Expr::Add(lhs, rhs) => lhs.eval(env) + rhs.eval(env),
+            Expr::Sub(lhs, rhs) => lhs.eval(env) - rhs.eval(env),
+            Expr::Mul(lhs, rhs) => lhs.eval(env) * rhs.eval(env),
+            Expr::Neg(expr) => -expr.eval(env),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("<=", Comparison::Le),
+        (">=", Comparison::Ge),
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        ("<", Comparison::Lt),
+        (">", Comparison::Gt),
+    ];
+
+    fn holds(self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            Comparison::Lt => lhs < rhs,
+            Comparison::Le => lhs <= rhs,
+            Comparison::Gt => lhs > rhs,
+            Comparison::Ge => lhs >= rhs,
+            Comparison::Eq => lhs == rhs,
+            Comparison::Ne => lhs != rhs,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Access {
+    id: usize,
+    array: usize,
+    subscripts: Vec<Expr>,
+    write: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Stmt {
+    Loop {
+        lb: Expr,
+        ub: Expr,
+        inclusive: bool,
+        step: i64,
+        parallel: bool,
+        body: Vec<Stmt>,
+    },
+    If {
+        lhs: Expr,
+        comparison: Comparison,
+        rhs: Expr,
+        body: Vec<Stmt>,
+    },
+    Access(Access),
+}
+
+#[derive(Debug, Clone)]
+struct Array {
+    name: String,
+    dims: Vec<Expr>,
+}
+
+/// A loop nest described in text, so that kernels can be traced without writing them against
+/// the DACE AST and recompiling.
+///
+/// ```text
+/// # mvt, first half
+/// params n
+/// array A[n][n], x1[n], y1[n]
+/// for i in 0..n {
+///   for j in 0..n {
+///     x1[i] = x1[i] A[i][j] y1[j]
+///   }
+/// }
+/// ```
+///
+/// `params` names the size parameters, in the order they are given, and `array` declares
+/// row-major arrays whose dimensions are expressions of the parameters. A loop runs its
+/// variable from the first bound up to the second, excluded with `..` and included with `..=`,
+/// by an optional `step` that may be negative to count down. `parallel for` marks a loop whose
+/// iterations may be run by different threads. Loops nested in a parallel loop run in its
+/// iterations, and a loop nest without parallel loops runs on one thread. `if` takes a
+/// comparison (`<`, `<=`, `>`, `>=`, `==`, `!=`) and guards the block that follows. Any other
+/// line lists array references: those after `=` are read in order, then the one before it is
+/// written. Bounds, conditions and subscripts are integer expressions with `+`, `-`, `*` and
+/// parentheses over the parameters and enclosing loop variables. `#` starts a comment.
+///
+/// References are numbered in the order they are executed within a statement (reads, then the
+/// write) and in the order of the text across statements.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub params: Vec<String>,
+    arrays: Vec<Array>,
+    /// every reference, by ID
+    references: Vec<RefInfo>,
+    body: Vec<Stmt>,
+}
+
+enum Block {
+    Top,
+    Loop {
+        lb: Expr,
+        ub: Expr,
+        inclusive: bool,
+        step: i64,
+        parallel: bool,
+    },
+    If {
+        lhs: Expr,
+        comparison: Comparison,
+        rhs: Expr,
+    },
+}
+
+impl Program {
+    pub fn parse(text: &str) -> Result<Program, String> {
+        let mut params: Vec<String> = Vec::new();
+        let mut arrays: Vec<Array> = Vec::new();
+        // the parameters, then the variables of the enclosing loops
+        let mut scope: Vec<String> = Vec::new();
+        let mut blocks: Vec<(Block, Vec<Stmt>)> = vec![(Block::Top, Vec::new())];
+        let mut references: Vec<RefInfo> = Vec::new();
+        // the IDs of the enclosing loops, numbered in the order of the text
+        let mut loops: Vec<usize> = Vec::new();
+        let mut next_loop = 0;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(names) = line.strip_prefix("params ") {
+                if !arrays.is_empty() || blocks.len() > 1 || !blocks[0].1.is_empty() {
+                    return Err(error("params must come first".to_string()));
+                }
+                for name in names.split([' ', ',']).filter(|name| !name.is_empty()) {
+                    if !is_identifier(name) {
+                        return Err(error(format!("'{}' is not a parameter name", name)));
+                    }
+                    params.push(name.to_string());
+                }
+                scope = params.clone();
+            } else if let Some(decls) = line.strip_prefix("array ") {
+                for decl in decls.split(',') {
+                    let (name, dims) = parse_ref(decl.trim(), &params).map_err(error)?;
+                    if dims.is_empty() {
+                        return Err(error(format!("array {} has no dimensions", name)));
+                    }
+                    if arrays.iter().any(|array| array.name == name) {
+                        return Err(error(format!("array {} is declared twice", name)));
+                    }
+                    arrays.push(Array { name, dims });
+                }
+            } else if let Some((parallel, header)) = line
+                .strip_prefix("for ")
+                .map(|header| (false, header))
+                .or_else(|| {
+                    line.strip_prefix("parallel for ")
+                        .map(|header| (true, header))
+                })
+            {
+                let header = header
+                    .strip_suffix('{')
+                    .ok_or_else(|| error("a loop header must end with '{'".to_string()))?;
+                let (var, range) = header
+                    .split_once(" in ")
+                    .ok_or_else(|| error("expected 'for VAR in LB..UB'".to_string()))?;
+                let var = var.trim();
+                if !is_identifier(var) || scope.iter().any(|name| name == var) {
+                    return Err(error(format!("'{}' is not a new loop variable", var)));
+                }
+                let (range, step) = match range.split_once(" step ") {
+                    Some((range, step)) => {
+                        let step = step
+                            .trim()
+                            .parse::<i64>()
+                            .ok()
+                            .filter(|&step| step != 0)
+                            .ok_or_else(|| error(format!("invalid step '{}'", step.trim())))?;
+                        (range, step)
+                    }
+                    None => (range, 1),
+                };
+                let (lb, ub, inclusive) = match range.split_once("..=") {
+                    Some((lb, ub)) => (lb, ub, true),
+                    None => {
+                        let (lb, ub) = range
+                            .split_once("..")
+                            .ok_or_else(|| error("expected a range 'LB..UB'".to_string()))?;
+                        (lb, ub, false)
+                    }
+                };
+                let lb = parse_expr(lb, &scope).map_err(error)?;
+                let ub = parse_expr(ub, &scope).map_err(error)?;
+                scope.push(var.to_string());
+                loops.push(next_loop);
+                next_loop += 1;
+                let block = Block::Loop {
+                    lb,
+                    ub,
+                    inclusive,
+                    step,
+                    parallel,
+                };
+                blocks.push((block, Vec::new()));
+            } else if let Some(condition) = line.strip_prefix("if ") {
+                let condition = condition
+                    .strip_suffix('{')
+                    .ok_or_else(|| error("a condition must end with '{'".to_string()))?;
+                let (lhs, comparison, rhs) = Comparison::OPERATORS
+                    .iter()
+                    .find_map(|&(operator, comparison)| {
+                        let (lhs, rhs) = condition.split_once(operator)?;
+                        Some((lhs, comparison, rhs))
+                    })
+                    .ok_or_else(|| error("expected a comparison".to_string()))?;
+                let block = Block::If {
+                    lhs: parse_expr(lhs, &scope).map_err(error)?,
+                    comparison,
+                    rhs: parse_expr(rhs, &scope).map_err(error)?,
+                };
+                blocks.push((block, Vec::new()));
+            } else if line == "}" {
+                let (block, body) = blocks.pop().unwrap();
+                let stmt = match block {
+                    Block::Top => return Err(error("unmatched '}'".to_string())),
+                    Block::Loop {
+                        lb,
+                        ub,
+                        inclusive,
+                        step,
+                        parallel,
+                    } => {
+                        scope.pop();
+                        loops.pop();
+                        Stmt::Loop {
+                            lb,
+                            ub,
+                            inclusive,
+                            step,
+                            parallel,
+                            body,
+                        }
+                    }
+                    Block::If {
+                        lhs,
+                        comparison,
+                        rhs,
+                    } => Stmt::If {
+                        lhs,
+                        comparison,
+                        rhs,
+                        body,
+                    },
+                };
+                blocks.last_mut().unwrap().1.push(stmt);
+            } else {
+                let (target, reads) = match line.split_once('=') {
+                    Some((target, reads)) => (Some(target.trim()), reads),
+                    None => (None, line),
+                };
+                let mut refs: Vec<(&str, bool)> = split_refs(reads)
+                    .into_iter()
+                    .map(|text| (text, false))
+                    .collect();
+                refs.extend(target.map(|text| (text, true)));
+                if refs.is_empty() {
+                    return Err(error("expected array references".to_string()));
+                }
+                for (text, write) in refs {
+                    let (name, subscripts) = parse_ref(text, &scope).map_err(error)?;
+                    let array = arrays
+                        .iter()
+                        .position(|array| array.name == name)
+                        .ok_or_else(|| error(format!("array {} is not declared", name)))?;
+                    if subscripts.len() != arrays[array].dims.len() {
+                        return Err(error(format!(
+                            "array {} has {} dimensions but {} subscripts",
+                            name,
+                            arrays[array].dims.len(),
+                            subscripts.len()
+                        )));
+                    }
+                    let access = Access {
+                        id: references.len(),
+                        array,
+                        subscripts,
+                        write,
+                    };
+                    references.push(RefInfo {
+                        reference: access.id,
+                        array: name.clone(),
+                        subscripts: text[text.find('[').unwrap()..]
+                            .chars()
+                            .filter(|c| !c.is_whitespace())
+                            .collect(),
+                        depth: loops.len(),
+                        loops: loops.clone(),
+                    });
+                    blocks.last_mut().unwrap().1.push(Stmt::Access(access));
+                }
+            }
+        }
+
+        if blocks.len() > 1 {
+            return Err("unclosed '{' at the end of the loop nest".to_string());
+        }
+        let (_, body) = blocks.pop().unwrap();
+        Ok(Program {
+            params,
+            arrays,
+            references,
+            body,
+        })
+    }
+
+    /// Give the program its parameters and place its arrays in `layout`.
+    pub fn bind(&self, params: &[usize], layout: &mut DataLayout) -> BoundProgram<'_> {
+        assert_eq!(params.len(), self.params.len());
+        let params: Vec<i64> = params.iter().map(|&param| param as i64).collect();
+        let dims: Vec<Vec<i64>> = self
+            .arrays
+            .iter()
+            .map(|array| array.dims.iter().map(|dim| dim.eval(&params)).collect())
+            .collect();
+        layout.place(self.arrays.iter().zip(&dims).map(|(array, dims)| {
+            let elements = dims.iter().product::<i64>().max(0) as usize;
+            (array.name.clone(), elements)
+        }));
+        BoundProgram {
+            program: self,
+            params,
+            dims,
+        }
+    }
+}
+
+/// A program with its parameters, ready to be traced.
+pub struct BoundProgram<'a> {
+    program: &'a Program,
+    params: Vec<i64>,
+    /// the dimensions of every array
+    dims: Vec<Vec<i64>>,
+}
+
+impl BoundProgram<'_> {
+    fn run(
+        &self,
+        stmts: &[Stmt],
+        env: &mut Vec<i64>,
+        position: &mut Position,
+        layout: &DataLayout,
+        visit: &mut dyn FnMut(Position, usize, u64, bool),
+    ) {
+        for stmt in stmts {
+            match stmt {
+                Stmt::Loop {
+                    lb,
+                    ub,
+                    inclusive,
+                    step,
+                    parallel,
+                    body,
+                } => {
+                    let ub = ub.eval(env);
+                    let lb = lb.eval(env);
+                    let runs = |i: i64| match (*step > 0, *inclusive) {
+                        (true, false) => i < ub,
+                        (true, true) => i <= ub,
+                        (false, false) => i > ub,
+                        (false, true) => i >= ub,
+                    };
+                    // a parallel loop nested in another one runs in its iterations
+                    let parallel = *parallel && position.iteration.is_none();
+                    let mut count = 0;
+                    if parallel {
+                        let mut i = lb;
+                        while runs(i) {
+                            count += 1;
+                            i += step;
+                        }
+                        position.parallel_loops += 1;
+                    }
+
+                    let mut i = lb;
+                    let mut index = 0;
+                    while runs(i) {
+                        if parallel {
+                            position.iteration = Some((index, count));
+                        }
+                        env.push(i);
+                        self.run(body, env, position, layout, visit);
+                        env.pop();
+                        i += step;
+                        index += 1;
+                    }
+                    if parallel {
+                        position.iteration = None;
+                    }
+                }
+                Stmt::If {
+                    lhs,
+                    comparison,
+                    rhs,
+                    body,
+                } => {
+                    if comparison.holds(lhs.eval(env), rhs.eval(env)) {
+                        self.run(body, env, position, layout, visit);
+                    }
+                }
+                Stmt::Access(access) => {
+                    let array = &self.program.arrays[access.array];
+                    let dims = &self.dims[access.array];
+                    let offset = access
+                        .subscripts
+                        .iter()
+                        .zip(dims)
+                        .fold(0, |acc, (sub, &dim)| {
+                            let index = sub.eval(env);
+                            if index < 0 || index >= dim {
+                                panic!(
+                                    "index {} of array {} is out of bounds (dimension {})",
+                                    index, array.name, dim
+                                );
+                            }
+                            acc * dim + index
+                        });
+                    let address = layout.line_of(&array.name, offset as usize) as u64;
+                    visit(*position, access.id, address, access.write);
+                }
+            }
+        }
+    }
+}
+
+impl LoopNest for BoundProgram<'_> {
+    fn for_each_parallel_access(
+        &self,
+        layout: &DataLayout,
+        visit: &mut dyn FnMut(Position, usize, u64, bool),
+    ) {
+        let mut env = self.params.clone();
+        let mut position = Position::default();
+        self.run(&self.program.body, &mut env, &mut position, layout, visit);
+    }
+
+    fn references(&self) -> Vec<RefInfo> {
+        self.program.references.clone()
+    }
+}
+
+pub(crate) fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a list of array references, separated by spaces or commas, at the top level.
+fn split_refs(text: &str) -> Vec<&str> {
+    let mut refs = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ' ' | ',' | '\t' if depth == 0 => {
+                if let Some(s) = start.take() {
+                    refs.push(&text[s..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    refs.extend(start.map(|s| &text[s..]));
+    refs
+}
+
+/// Parse `NAME[expr][expr]...`.
+fn parse_ref(text: &str, scope: &[String]) -> Result<(String, Vec<Expr>), String> {
+    let (name, mut rest) = text.split_at(text.find('[').unwrap_or(text.len()));
+    let name = name.trim();
+    if !is_identifier(name) {
+        return Err(format!("'{}' is not an array reference", text));
+    }
+    let mut subscripts = Vec::new();
+    while !rest.is_empty() {
+        let close = rest
+            .find(']')
+            .filter(|_| rest.starts_with('['))
+            .ok_or_else(|| format!("'{}' is not an array reference", text))?;
+        subscripts.push(parse_expr(&rest[1..close], scope)?);
+        rest = rest[close + 1..].trim_start();
+    }
+    Ok((name.to_string(), subscripts))
+}
+
+fn parse_expr(text: &str, scope: &[String]) -> Result<Expr, String> {
+    let tokens = tokenize(text)?;
+    let mut parser = ExprParser {
+        tokens: &tokens,
+        pos: 0,
+        scope,
+    };
+    let expr = parser.sum()?;
+    if parser.pos != tokens.len() {
+        return Err(format!(
+            "unexpected '{}' in '{}'",
+            tokens[parser.pos],
+            text.trim()
+        ));
+    }
+    Ok(expr)
+}
+
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut token = String::new();
+            while let Some(&c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        } else if "+-*()".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else {
+            return Err(format!("unexpected '{}' in '{}'", c, text.trim()));
+        }
+    }
+    if tokens.is_empty() {
+        return Err("missing expression".to_string());
+    }
+    Ok(tokens)
+}
+
+struct ExprParser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+    scope: &'a [String],
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(operator) = self.peek().filter(|&token| token == "+" || token == "-") {
+            let add = operator == "+";
+            self.pos += 1;
+            let rhs = self.product()?;
+            expr = if add {
+                Expr::Add(Box::new(expr), Box::new(rhs))
+            } else {
+                Expr::Sub(Box::new(expr), Box::new(rhs))
+            };
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some("*") {
+            self.pos += 1;
+            expr = Expr::Mul(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let token = self
+            .peek()
+            .ok_or_else(|| "incomplete expression".to_string())?
+            .to_string();
+        self.pos += 1;
+        match token.as_str() {
+            "-" => Ok(Expr::Neg(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.sum()?;
+                if self.peek() != Some(")") {
+                    return Err("missing ')'".to_string());
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => token
+                .parse::<i64>()
+                .map(Expr::Const)
+                .map_err(|_| format!("invalid number '{}'", token)),
+            _ if is_identifier(&token) => self
+                .scope
+                .iter()
+                .rposition(|name| *name == token)
+                .map(Expr::Var)
+                .ok_or_else(|| format!("unknown variable '{}'", token)),
+            _ => Err(format!("unexpected '{}'", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fxhash::FxHashMap;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn parses_and_traces_every_bundled_loop_nest() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("loops");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "loop") {
+                continue;
+            }
+            let text = fs::read_to_string(&path).unwrap();
+            let program = Program::parse(&text)
+                .unwrap_or_else(|message| panic!("{}: {}", path.display(), message));
+            assert!(!program.params.is_empty(), "{}", path.display());
+
+            // every subscript stays in bounds at a small size
+            let mut layout = DataLayout::default();
+            let bound = program.bind(&vec![6; program.params.len()], &mut layout);
+            let mut accesses = 0;
+            bound.for_each_parallel_access(&layout, &mut |_, reference, _, _| {
+                assert!(reference < program.references.len());
+                accesses += 1;
+            });
+            assert!(accesses > 0, "{}", path.display());
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn traces_the_exact_accesses_of_a_nest() {
+        let program = Program::parse(
+            "# the strictly upper triangle, by rows, from the right
+            params n
+            array A[n][n], x[n]
+            x[n-1] =
+            parallel for i in 0..n {
+              for j in n-1..=i step -1 {
+                if j != i {
+                  x[i] = x[i], A[i][ j ]
+                }
+              }
+            }",
+        )
+        .unwrap();
+        let labels: Vec<String> = program.references.iter().map(RefInfo::label).collect();
+        assert_eq!(labels, ["x[n-1]", "x[i]", "A[i][j]", "x[i]"]);
+        assert_eq!(program.references[2].depth, 2);
+        assert_eq!(program.references[2].loops, [0, 1]);
+
+        // with one-byte elements and lines, A takes lines 0 to 8 and x lines 9 to 11
+        let mut layout = DataLayout::new(1, 1, FxHashMap::default());
+        let bound = program.bind(&[3], &mut layout);
+        let mut accesses = Vec::new();
+        bound.for_each_parallel_access(&layout, &mut |position, reference, address, write| {
+            accesses.push((
+                position.parallel_loops,
+                position.iteration,
+                reference,
+                address,
+                write,
+            ));
+        });
+        let row = |i: usize, j: u64| {
+            let iteration = Some((i, 3));
+            [
+                (1, iteration, 1, 9 + i as u64, false),
+                (1, iteration, 2, 3 * i as u64 + j, false),
+                (1, iteration, 3, 9 + i as u64, true),
+            ]
+        };
+        let mut expected = vec![(0, None, 0, 11, true)];
+        expected.extend(row(0, 2));
+        expected.extend(row(0, 1));
+        expected.extend(row(1, 2));
+        assert_eq!(accesses, expected);
+    }
+
+    #[test]
+    fn rejects_malformed_loop_nests() {
+        for (text, error) in [
+            ("array A[4]\nparams n", "line 2: params must come first"),
+            ("params n 1m", "'1m' is not a parameter name"),
+            ("params n\narray A", "array A has no dimensions"),
+            ("params n\narray A[n], A[n]", "array A is declared twice"),
+            ("params n\narray A[n][2*m]", "unknown variable 'm'"),
+            ("params n\nfor i in 0..n", "a loop header must end with '{'"),
+            ("params n\nfor i 0..n {", "expected 'for VAR in LB..UB'"),
+            (
+                "params n\nfor n in 0..n {",
+                "'n' is not a new loop variable",
+            ),
+            ("params n\nfor i in 0..n step 0 {", "invalid step '0'"),
+            ("params n\nfor i in 0 n {", "expected a range 'LB..UB'"),
+            ("params n\nif n {", "expected a comparison"),
+            ("params n\nif n > 0", "a condition must end with '{'"),
+            ("params n\n}", "line 2: unmatched '}'"),
+            ("params n\nfor i in 0..n {", "unclosed '{'"),
+            ("params n\narray A[n]\nB[0] =", "array B is not declared"),
+            (
+                "params n\narray A[n]\nA[0][0] =",
+                "array A has 1 dimensions but 2 subscripts",
+            ),
+            (
+                "params n\narray A[n]\nA[0 =",
+                "'A[0' is not an array reference",
+            ),
+            ("params n\narray A[n]\nA[n/2] =", "unexpected '/' in 'n/2'"),
+            ("params n\narray A[n]\nA[n n] =", "unexpected 'n' in 'n n'"),
+            ("params n\narray A[n]\nA[(n] =", "missing ')'"),
+            ("params n\narray A[n]\nA[n+] =", "incomplete expression"),
+            ("params n\narray A[n]\nA[] =", "missing expression"),
+        ] {
+            match Program::parse(text) {
+                Ok(_) => panic!("'{}' was accepted", text),
+                Err(message) => assert!(message.contains(error), "'{}': {}", text, message),
+            }
+        }
+    }
+}
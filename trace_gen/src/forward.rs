@@ -0,0 +1,248 @@
+use crate::trace_writer::{Columns, TraceFormat, TraceRecord, TraceWriter};
+use fxhash::FxHashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+
+/// The number of records read and written at a time.
+const BLOCK_RECORDS: usize = 1 << 16;
+
+/// Add forward reuse intervals to the binary trace at `input`, which only holds backward ones,
+/// and write the result to `output` in `format`. Thread IDs are kept.
+///
+/// The forward reuse interval of an access is only known at the next access to its line, so
+/// the trace is read backwards, a block at a time, keeping the time of the next access to every
+/// line. Binary records have a fixed size, so each block is written at its place in the output
+/// as soon as it is complete; a text trace is then written from that binary one.
+pub fn add_forward_intervals(input: &str, output: &str, format: TraceFormat) -> io::Result<()> {
+    let mut input_file = File::open(input)?;
+    let mut magic = [0; 8];
+    input_file.read_exact(&mut magic)?;
+    let input_columns = Columns::from_magic(&magic)
+        .filter(|columns| !columns.forward)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} is not a binary trace without forward reuse intervals",
+                    input
+                ),
+            )
+        })?;
+    let output_columns = Columns {
+        forward: true,
+        ..input_columns
+    };
+    let input_size = input_columns.binary_size();
+    let output_size = output_columns.binary_size();
+    let records = (input_file.metadata()?.len() - magic.len() as u64) / input_size as u64;
+
+    let binary_path = match format {
+        TraceFormat::Binary => output.to_string(),
+        TraceFormat::Text => format!("{}.forward", output),
+    };
+    let mut binary = File::create(&binary_path)?;
+    binary.write_all(output_columns.magic())?;
+
+    let mut next_access: FxHashMap<u64, u64> = FxHashMap::default();
+    let mut input_block = Vec::new();
+    let mut output_block = Vec::new();
+    let mut end = records;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK_RECORDS as u64);
+        let count = (end - start) as usize;
+        input_file.seek(SeekFrom::Start(
+            magic.len() as u64 + start * input_size as u64,
+        ))?;
+        input_block.resize(count * input_size, 0);
+        input_file.read_exact(&mut input_block)?;
+        output_block.resize(count * output_size, 0);
+
+        for i in (0..count).rev() {
+            let mut record =
+                TraceRecord::decode(&input_block[i * input_size..][..input_size], input_columns);
+            record.forward_reuse_interval = next_access
+                .insert(record.address, record.time)
+                .map(|next| next - record.time);
+            record.encode(
+                &mut output_block[i * output_size..][..output_size],
+                output_columns,
+            );
+        }
+
+        binary.seek(SeekFrom::Start(
+            magic.len() as u64 + start * output_size as u64,
+        ))?;
+        binary.write_all(&output_block)?;
+        end = start;
+    }
+
+    if format == TraceFormat::Text {
+        let mut reader = BufReader::new(File::open(&binary_path)?);
+        reader.seek(SeekFrom::Start(magic.len() as u64))?;
+        let mut writer = TraceWriter::create(output, TraceFormat::Text, output_columns)?;
+        let mut bytes = vec![0; output_size];
+        for _ in 0..records {
+            reader.read_exact(&mut bytes)?;
+            writer.write(&TraceRecord::decode(&bytes, output_columns))?;
+        }
+        writer.finish()?;
+        fs::remove_file(&binary_path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace_writer::FORWARD_THREAD_BINARY_MAGIC;
+    use std::env;
+    use std::path::Path;
+    use std::process;
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("trace_gen_{}_{}", process::id(), name))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Write a binary trace with thread IDs of the accesses to `addresses`, with their backward
+    /// reuse intervals, and return their forward reuse intervals.
+    fn write_trace(path: &str, addresses: &[u64]) -> Vec<Option<u64>> {
+        let columns = Columns {
+            forward: false,
+            thread: true,
+        };
+        let mut writer = TraceWriter::create(path, TraceFormat::Binary, columns).unwrap();
+        let mut last: FxHashMap<u64, u64> = FxHashMap::default();
+        let mut forward = vec![None; addresses.len()];
+        for (time, &address) in addresses.iter().enumerate() {
+            let time = time as u64;
+            let reuse_interval = last.insert(address, time).map(|previous| time - previous);
+            if let Some(reuse_interval) = reuse_interval {
+                forward[(time - reuse_interval) as usize] = Some(reuse_interval);
+            }
+            let record = TraceRecord {
+                reference: address % 3,
+                reuse_interval,
+                address,
+                time,
+                write: time % 2 == 1,
+                forward_reuse_interval: None,
+                thread: Some(time as usize % 2),
+            };
+            writer.write(&record).unwrap();
+        }
+        writer.finish().unwrap();
+        forward
+    }
+
+    /// The records of the binary trace at `path`, which has forward reuse intervals and
+    /// threads.
+    fn read_trace(path: &str) -> Vec<TraceRecord> {
+        let bytes = fs::read(path).unwrap();
+        assert_eq!(&bytes[..8], FORWARD_THREAD_BINARY_MAGIC);
+        let columns = Columns {
+            forward: true,
+            thread: true,
+        };
+        bytes[8..]
+            .chunks(columns.binary_size())
+            .map(|record| TraceRecord::decode(record, columns))
+            .collect()
+    }
+
+    #[test]
+    fn adds_forward_intervals_to_binary_and_text_traces() {
+        let input = temp_path("forward_input.bin");
+        let output = temp_path("forward_output.bin");
+        let text = temp_path("forward_output.txt");
+        // the last access to every line is never reused
+        let forward = write_trace(&input, &[1, 2, 1, 3, 2, 1]);
+        assert_eq!(forward, [Some(2), Some(3), Some(3), None, None, None]);
+
+        add_forward_intervals(&input, &output, TraceFormat::Binary).unwrap();
+        let records = read_trace(&output);
+        let fields: Vec<_> = records
+            .iter()
+            .map(|record| {
+                (
+                    record.reference,
+                    record.reuse_interval,
+                    record.address,
+                    record.time,
+                    record.write,
+                    record.forward_reuse_interval,
+                    record.thread,
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                (1, None, 1, 0, false, Some(2), Some(0)),
+                (2, None, 2, 1, true, Some(3), Some(1)),
+                (1, Some(2), 1, 2, false, Some(3), Some(0)),
+                (0, None, 3, 3, true, None, Some(1)),
+                (2, Some(3), 2, 4, false, None, Some(0)),
+                (1, Some(3), 1, 5, true, None, Some(1)),
+            ]
+        );
+
+        add_forward_intervals(&input, &text, TraceFormat::Text).unwrap();
+        assert_eq!(
+            fs::read_to_string(&text).unwrap(),
+            "00000001,ffffffff,00000001,0,R,00000002,0\n\
+             00000002,ffffffff,00000002,1,W,00000003,1\n\
+             00000001,00000002,00000001,2,R,00000003,0\n\
+             00000000,ffffffff,00000003,3,W,ffffffff,1\n\
+             00000002,00000003,00000002,4,R,ffffffff,0\n\
+             00000001,00000003,00000001,5,W,ffffffff,1\n"
+        );
+        // the binary trace the text one is written from is removed
+        assert!(!Path::new(&format!("{}.forward", text)).exists());
+
+        // a trace that already has forward reuse intervals is rejected
+        assert!(add_forward_intervals(&output, &text, TraceFormat::Binary).is_err());
+
+        for path in [input, output, text] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn carries_reuse_across_blocks() {
+        let input = temp_path("forward_blocks_input.bin");
+        let output = temp_path("forward_blocks_output.bin");
+        // the trace is read from its end, so the first block is its last BLOCK_RECORDS accesses;
+        // lines are reused within a block, across the boundary at time 100 and never
+        let addresses: Vec<u64> = (0..BLOCK_RECORDS as u64 + 100)
+            .map(|time| match time % 5 {
+                0 => time % 7,
+                1 => 1000 + time % 65_535,
+                _ => 2000 + time,
+            })
+            .collect();
+        let forward = write_trace(&input, &addresses);
+
+        add_forward_intervals(&input, &output, TraceFormat::Binary).unwrap();
+        let records = read_trace(&output);
+        assert_eq!(records.len(), addresses.len());
+        for (time, record) in records.iter().enumerate() {
+            assert_eq!(record.time, time as u64);
+            assert_eq!(record.address, addresses[time]);
+            assert_eq!(
+                record.forward_reuse_interval, forward[time],
+                "time {}",
+                time
+            );
+        }
+        assert!(forward[..100]
+            .iter()
+            .any(|&ri| ri.is_some_and(|ri| ri > 65_000)));
+
+        for path in [input, output] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use crate::dsl::Program;
+use crate::ri_utils::DataLayout;
+use crate::synthetic::Workload;
+use crate::tracer::{DaceLoopNest, LoopNest};
+use dace::ast::Node;
+use dace_tests::matmul;
+use dace_tests::polybench_simplify::{
+    _2mm, _3mm, cholesky, gemm, gramschmidt_trace, lu, mvt, syr2d, syrk, trisolv, trmm_trace,
+};
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+type DaceBuilder = fn(&[usize]) -> Rc<Node>;
+
+/// The kernels of DACE, with the names of their parameters.
+const DACE_KERNELS: &[(&str, &[&str], DaceBuilder)] = &[
+    ("2mm", &["ni", "nj", "nk", "nl"], |p| {
+        _2mm(p[0], p[1], p[2], p[3])
+    }),
+    ("3mm", &["ni", "nj", "nk", "nl", "nm"], |p| {
+        _3mm(p[0], p[1], p[2], p[3], p[4])
+    }),
+    ("cholesky", &["n"], |p| cholesky(p[0])),
+    ("gemm", &["n"], |p| gemm(p[0])),
+    ("gramschmidt_trace", &["n", "m"], |p| {
+        gramschmidt_trace(p[0], p[1])
+    }),
+    ("lu", &["n"], |p| lu(p[0])),
+    ("matmul", &["n"], |p| matmul(p[0])),
+    ("mvt", &["n"], |p| mvt(p[0])),
+    ("syr2d", &["n", "m"], |p| syr2d(p[0], p[1])),
+    ("syrk", &["n", "m"], |p| syrk(p[0], p[1])),
+    ("trisolv", &["n"], |p| trisolv(p[0])),
+    ("trmm_trace", &["n", "m"], |p| trmm_trace(p[0], p[1])),
+];
+
+/// The PolyBench kernels DACE does not provide, in the loop nest language of `Program`.
+const TEXT_KERNELS: &[(&str, &str)] = &[
+    ("adi", include_str!("../loops/adi.loop")),
+    ("atax", include_str!("../loops/atax.loop")),
+    ("bicg", include_str!("../loops/bicg.loop")),
+    ("correlation", include_str!("../loops/correlation.loop")),
+    ("covariance", include_str!("../loops/covariance.loop")),
+    ("doitgen", include_str!("../loops/doitgen.loop")),
+    ("durbin", include_str!("../loops/durbin.loop")),
+    ("fdtd-2d", include_str!("../loops/fdtd-2d.loop")),
+    (
+        "floyd-warshall",
+        include_str!("../loops/floyd-warshall.loop"),
+    ),
+    ("gemver", include_str!("../loops/gemver.loop")),
+    ("gesummv", include_str!("../loops/gesummv.loop")),
+    ("heat-3d", include_str!("../loops/heat-3d.loop")),
+    ("jacobi-1d", include_str!("../loops/jacobi-1d.loop")),
+    ("jacobi-2d", include_str!("../loops/jacobi-2d.loop")),
+    ("ludcmp", include_str!("../loops/ludcmp.loop")),
+    ("nussinov", include_str!("../loops/nussinov.loop")),
+    ("seidel-2d", include_str!("../loops/seidel-2d.loop")),
+    ("symm", include_str!("../loops/symm.loop")),
+    ("syr2k", include_str!("../loops/syr2k.loop")),
+];
+
+/// A loop nest that can be traced, built from its size parameters.
+pub struct Kernel {
+    pub name: String,
+    /// the names of the parameters `build` takes, in order
+    pub params: Vec<String>,
+    source: Source,
+}
+
+enum Source {
+    Dace(DaceBuilder),
+    Text(Program),
+    Synthetic(Workload),
+}
+
+impl Kernel {
+    /// A kernel described in the loop nest language of `Program`.
+    pub fn from_text(name: &str, text: &str) -> Result<Kernel, String> {
+        let program = Program::parse(text).map_err(|message| format!("{}: {}", name, message))?;
+        Ok(Kernel {
+            name: name.to_string(),
+            params: program.params.clone(),
+            source: Source::Text(program),
+        })
+    }
+
+    /// A synthetic workload, such as `zipf(size=4096,accesses=100000)`, which takes no
+    /// parameters.
+    pub fn from_synthetic(spec: &str) -> Result<Kernel, String> {
+        let workload = Workload::parse(spec).map_err(|message| format!("{}: {}", spec, message))?;
+        Ok(Kernel {
+            name: spec.to_string(),
+            params: Vec::new(),
+            source: Source::Synthetic(workload),
+        })
+    }
+
+    /// Build the loop nest and place its arrays in `layout`, checking that `params` has one
+    /// value per parameter.
+    pub fn build(
+        &self,
+        params: &[usize],
+        layout: &mut DataLayout,
+    ) -> Result<Box<dyn LoopNest + '_>, String> {
+        if params.len() != self.params.len() {
+            return Err(format!(
+                "{} takes {} parameter{} ({}), but {} {} given",
+                self.name,
+                self.params.len(),
+                if self.params.len() == 1 { "" } else { "s" },
+                self.params.join(", "),
+                params.len(),
+                if params.len() == 1 { "was" } else { "were" }
+            ));
+        }
+        Ok(match &self.source {
+            Source::Dace(build) => Box::new(DaceLoopNest::new(build(params), layout)),
+            Source::Text(program) => Box::new(program.bind(params, layout)),
+            Source::Synthetic(workload) => {
+                workload.place_arrays(layout);
+                Box::new(workload.clone())
+            }
+        })
+    }
+}
+
+/// Every built-in kernel, sorted by name.
+pub fn kernels() -> Vec<Kernel> {
+    let mut kernels: Vec<Kernel> = DACE_KERNELS
+        .iter()
+        .map(|&(name, params, build)| Kernel {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            source: Source::Dace(build),
+        })
+        .chain(
+            TEXT_KERNELS
+                .iter()
+                .map(|&(name, text)| Kernel::from_text(name, text).unwrap()),
+        )
+        .collect();
+    kernels.sort_by(|a, b| a.name.cmp(&b.name));
+    kernels
+}
+
+/// The built-in kernel called `name`, the synthetic workload it describes if it has
+/// parentheses, or else the kernel described by the file at that path.
+pub fn find_kernel(name: &str) -> Result<Kernel, String> {
+    if name.contains('(') {
+        return Kernel::from_synthetic(name);
+    }
+    if let Some(kernel) = kernels().into_iter().find(|kernel| kernel.name == name) {
+        return Ok(kernel);
+    }
+    let path = Path::new(name);
+    if !path.is_file() {
+        return Err(format!(
+            "unknown kernel '{}' (see --list for the available kernels)",
+            name
+        ));
+    }
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", name, err))?;
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    Kernel::from_text(&stem, &text)
+}
@@ -0,0 +1,15 @@
+//! Reuse interval tracing of DACE loop nests, loop nest files and synthetic workloads, used by
+//! the `trace_gen` binary and, through its `trace_gen` feature, by the simulator.
+#![feature(get_mut_unchecked)]
+
+pub mod dsl;
+pub mod forward;
+pub mod kernels;
+pub mod parallel;
+pub mod ref_hist;
+pub mod ref_info;
+pub mod ri_utils;
+pub mod sampling;
+pub mod synthetic;
+pub mod trace_writer;
+pub mod tracer;
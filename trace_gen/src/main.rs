@@ -1,73 +1,289 @@
-#![feature(get_mut_unchecked)]
-
-mod ri_utils;
-mod sampling;
-use dace_tests::matmul;
-use dace_tests::polybench_simplify::{
-    _2mm, _3mm, cholesky, gemm, gramschmidt_trace, lu, mvt, syr2d, syrk, trisolv, trmm_trace,
-};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use fxhash::FxHashSet;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::path::Path;
 use std::{env, fs, time::Instant};
+use trace_gen::forward::add_forward_intervals;
+use trace_gen::kernels::{find_kernel, kernels};
+use trace_gen::parallel::{self, Schedule};
+use trace_gen::ref_info::write_ref_info;
+use trace_gen::ri_utils::{self, DataLayout};
+use trace_gen::sampling::{self, Sampler, SamplingMethod};
+use trace_gen::synthetic;
+use trace_gen::trace_writer::{Columns, TraceFormat, TraceWriter};
 use tracing_subscriber::EnvFilter;
 
+#[derive(Parser)]
+#[command(
+    name = "trace_gen",
+    about = "Generate the reuse interval trace of a PolyBench kernel, a loop nest file or a synthetic workload"
+)]
+struct Cli {
+    /// The kernel to trace: a built-in kernel (see --list), the path of a loop nest file or a
+    /// synthetic workload such as 'zipf(size=4096,accesses=100000,alpha=0.9)'
+    #[arg(required_unless_present = "list")]
+    kernel: Option<String>,
+
+    /// The kernel parameters, in the order given by --list, separated by spaces or commas
+    #[arg(value_delimiter = ',')]
+    params: Vec<usize>,
+
+    /// List the kernels and their parameters, and the synthetic patterns
+    #[arg(long)]
+    list: bool,
+
+    /// IDs of the references that store, separated by commas
+    #[arg(long, value_name = "REF_IDS", value_delimiter = ',')]
+    stores: Vec<usize>,
+
+    /// The size of an array element in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = ri_utils::DS, value_parser = parse_bytes)]
+    element_size: usize,
+
+    /// The size of a cache line in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = ri_utils::CLS, value_parser = parse_bytes)]
+    line_size: usize,
+
+    /// The element size of one array, if it differs from --element-size (repeatable)
+    #[arg(long, value_name = "ARRAY=BYTES", value_parser = parse_array_element_size)]
+    array_element_size: Vec<(String, usize)>,
+
+    /// The path of the trace file
+    #[arg(long, value_name = "TRACE_FILE", default_value = "out/output.txt")]
+    output: String,
+
+    /// The encoding of the trace file
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    format: TraceFormat,
+
+    /// The path of the reuse interval histogram file
+    #[arg(long, value_name = "HIST_FILE", default_value = "out/hist_output.txt")]
+    hist_output: String,
+
+    /// Sample this fraction of the accesses instead of writing the full trace
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = parse_rate,
+        conflicts_with_all = ["output", "format", "hist_output"]
+    )]
+    sample_rate: Option<f64>,
+
+    /// How the sampled accesses are chosen
+    #[arg(long, value_enum, default_value_t = SamplingMethod::Random, requires = "sample_rate")]
+    sampling: SamplingMethod,
+
+    /// Seed of the random number generator used for random sampling (default: random)
+    #[arg(long, requires = "sample_rate")]
+    seed: Option<u64>,
+
+    /// The path of the per-reference reuse interval histograms
+    #[arg(long, value_name = "HIST_FILE", default_value = "out/ref_hist.csv")]
+    ref_hist_output: String,
+
+    /// The path of the reference metadata: the array, subscripts and enclosing loops of every
+    /// reference ID
+    #[arg(long, value_name = "REF_FILE", default_value = "out/refs.csv")]
+    ref_info_output: String,
+
+    /// Add the forward reuse interval of every access to the trace
+    #[arg(long, conflicts_with_all = ["no_trace", "sample_rate"])]
+    forward: bool,
+
+    /// Write only the histograms, not the trace
+    #[arg(long, conflicts_with_all = ["output", "format"])]
+    no_trace: bool,
+
+    /// Run the parallel loops on this many simulated threads, writing the trace of every thread
+    /// besides the interleaved one
+    #[arg(long, value_parser = parse_count, conflicts_with = "sample_rate")]
+    threads: Option<usize>,
+
+    /// How the iterations of a parallel loop are divided among the threads
+    #[arg(long, value_enum, default_value_t = Schedule::Block, requires = "threads")]
+    schedule: Schedule,
+
+    /// The number of iterations in a chunk of the static schedule
+    #[arg(long, default_value_t = 1, value_parser = parse_count, requires = "threads")]
+    chunk: usize,
+}
+
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("Format:   exe   test_mode   data1,data2,data3,data4,...")
+    let cli = Cli::parse();
+
+    if cli.list {
+        for kernel in kernels() {
+            println!("{:<20}{}", kernel.name, kernel.params.join(" "));
+        }
+        println!("\nsynthetic patterns (all also take array, writes and seed):");
+        for (name, required, optional) in synthetic::PATTERNS {
+            let optional = optional.iter().map(|key| format!("[{}]", key));
+            let keys: Vec<String> = required
+                .iter()
+                .map(|key| key.to_string())
+                .chain(optional)
+                .collect();
+            println!("{:<20}{}", name, keys.join(" "));
+        }
+        return;
     }
 
-    let t_mode = &args[1];
-    let argdata = &args[2];
+    let name = cli.kernel.as_deref().unwrap();
+    let kernel = find_kernel(name).unwrap_or_else(|message| {
+        Cli::command()
+            .error(ErrorKind::InvalidValue, message)
+            .exit()
+    });
+
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_env("LOG_LEVEL"))
         .init();
-    // let mut wrapping_loop = Vec::new();
-    let split: Vec<&str> = argdata.split(',').collect();
-    let mut trace = match t_mode.as_str() {
-        "lu" => lu(split[0].parse::<usize>().unwrap()),
-        "trmm_trace" => trmm_trace(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-        ),
-        "mvt" => mvt(split[0].parse::<usize>().unwrap()),
-        "trisolv" => trisolv(split[0].parse::<usize>().unwrap()),
-        "syrk" => syrk(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-        ),
-        "syr2d" => syr2d(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-        ),
-        "gemm" => gemm(split[0].parse::<usize>().unwrap()),
-        "cholesky" => cholesky(split[0].parse::<usize>().unwrap()),
-        "gramschmidt_trace" => gramschmidt_trace(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-        ),
-        "3mm" => _3mm(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-            split[2].parse::<usize>().unwrap(),
-            split[3].parse::<usize>().unwrap(),
-            split[4].parse::<usize>().unwrap(),
-        ),
-        "2mm" => _2mm(
-            split[0].parse::<usize>().unwrap(),
-            split[1].parse::<usize>().unwrap(),
-            split[2].parse::<usize>().unwrap(),
-            split[3].parse::<usize>().unwrap(),
-        ),
-        "matmul" => matmul(split[0].parse::<usize>().unwrap()),
-        _ => matmul(split[0].parse::<usize>().unwrap()),
-    };
+    let mut layout = DataLayout::new(
+        cli.element_size,
+        cli.line_size,
+        cli.array_element_size.into_iter().collect(),
+    );
+    let nest = kernel
+        .build(&cli.params, &mut layout)
+        .unwrap_or_else(|message| {
+            Cli::command()
+                .error(ErrorKind::WrongNumberOfValues, message)
+                .exit()
+        });
+    write_ref_info(&cli.ref_info_output, &nest.references())
+        .expect("Unable to write reference metadata");
     let start = Instant::now();
-    let _hist = sampling::tracing_ri(&mut trace);
+    if let Some(rate) = cli.sample_rate {
+        let rng = match cli.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let sampler = Sampler::new(cli.sampling, rate, rng);
+        sampling::sample_ri(&*nest, &layout, sampler, &cli.ref_hist_output);
+    } else {
+        // DACE loop nests do not tell loads from stores, so the references that store are given
+        // by their ID
+        let stores: FxHashSet<usize> = cli.stores.into_iter().collect();
+        // the trace, then the trace of every thread
+        let mut outputs = vec![cli.output.clone()];
+        if let Some(threads) = cli.threads {
+            outputs.extend((0..threads).map(|thread| thread_trace_path(&cli.output, thread)));
+        }
+        if cli.no_trace {
+            outputs.clear();
+        }
+        // forward reuse intervals are added by a second pass over a binary trace with backward
+        // ones only
+        let backward_output = |output: &String| {
+            if cli.forward {
+                format!("{}.backward", output)
+            } else {
+                output.clone()
+            }
+        };
+        let backward_format = if cli.forward {
+            TraceFormat::Binary
+        } else {
+            cli.format
+        };
+        let columns = Columns {
+            forward: false,
+            thread: cli.threads.is_some(),
+        };
+        let mut writers: Vec<TraceWriter> = outputs
+            .iter()
+            .map(|output| {
+                TraceWriter::create(&backward_output(output), backward_format, columns)
+                    .expect("Unable to create trace file")
+            })
+            .collect();
+        if let Some(parent) = Path::new(&cli.hist_output).parent() {
+            fs::create_dir_all(parent).expect("Failed to create the output folder.");
+        }
+        match cli.threads {
+            Some(threads) => {
+                let writer = (!writers.is_empty()).then(|| writers.remove(0));
+                parallel::tracing_parallel_ri(
+                    &kernel,
+                    &cli.params,
+                    &layout,
+                    threads,
+                    cli.schedule,
+                    cli.chunk,
+                    &stores,
+                    writer,
+                    writers,
+                    &cli.hist_output,
+                    &cli.ref_hist_output,
+                );
+            }
+            None => {
+                sampling::tracing_ri(
+                    &*nest,
+                    &stores,
+                    &layout,
+                    writers.pop(),
+                    &cli.hist_output,
+                    &cli.ref_hist_output,
+                );
+            }
+        }
+        if cli.forward {
+            println!("Adding forward reuse intervals...");
+            for output in &outputs {
+                let backward_output = backward_output(output);
+                add_forward_intervals(&backward_output, output, cli.format)
+                    .expect("Unable to add forward reuse intervals");
+                fs::remove_file(&backward_output).expect("Unable to remove the backward trace");
+            }
+        }
+    }
     let end = Instant::now();
-    let t_mode = &args[1];
-    let argdata = &args[2];
 
-    let folder_name = format!("{}_{}", t_mode, argdata.replace(",", "_"));
-    fs::create_dir_all(&folder_name).expect("Failed to create the output folder.");
     println!("trace time: {:?}", end - start);
 }
+
+fn parse_bytes(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|&bytes| bytes > 0)
+        .ok_or_else(|| format!("'{}' is not a positive number of bytes", value))
+}
+
+fn parse_count(value: &str) -> Result<usize, String> {
+    value
+        .parse::<usize>()
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or_else(|| format!("'{}' is not a positive number", value))
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|&rate| rate > 0.0 && rate <= 1.0)
+        .ok_or_else(|| format!("'{}' is not a sample rate in (0, 1]", value))
+}
+
+fn parse_array_element_size(spec: &str) -> Result<(String, usize), String> {
+    let (array, size) = spec
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not of the form ARRAY=BYTES", spec))?;
+    Ok((array.to_string(), parse_bytes(size)?))
+}
+
+/// The path of the trace of one thread: `out/output.txt` becomes `out/output.thread0.txt`.
+fn thread_trace_path(output: &str, thread: usize) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.thread{}.{}", stem, thread, extension.to_string_lossy()),
+        None => format!("{}.thread{}", stem, thread),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
@@ -0,0 +1,325 @@
+use crate::kernels::Kernel;
+use crate::ri_utils::DataLayout;
+use crate::trace_writer::TraceWriter;
+use crate::tracer::Tracer;
+use clap::ValueEnum;
+use fxhash::FxHashSet;
+use hist::Hist;
+use std::fs::File;
+use std::io::Write;
+use std::mem;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::vec;
+
+/// The number of accesses a thread sends at a time.
+const BATCH: usize = 1 << 12;
+/// The number of batches a thread may run ahead of the interleaving.
+const BUFFER: usize = 16;
+
+/// How the iterations of a parallel loop are divided among the threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Schedule {
+    /// chunks of `--chunk` iterations, dealt to the threads in turn
+    Static,
+    /// one contiguous block of iterations per thread
+    Block,
+    /// single iterations, dealt to the threads in turn
+    Cyclic,
+}
+
+impl Schedule {
+    /// The thread that runs iteration `index` of a loop of `count` iterations.
+    pub fn owner(self, index: usize, count: usize, threads: usize, chunk: usize) -> usize {
+        match self {
+            Schedule::Static => index / chunk % threads,
+            Schedule::Block => index / count.div_ceil(threads),
+            Schedule::Cyclic => index % threads,
+        }
+    }
+}
+
+/// One access by a thread, with the region it is in.
+struct ThreadAccess {
+    region: usize,
+    ref_id: usize,
+    addr: u64,
+    write: bool,
+}
+
+/// The accesses of one thread, received in batches.
+struct ThreadStream {
+    receiver: Receiver<Vec<ThreadAccess>>,
+    batch: vec::IntoIter<ThreadAccess>,
+}
+
+impl Iterator for ThreadStream {
+    type Item = ThreadAccess;
+
+    fn next(&mut self) -> Option<ThreadAccess> {
+        loop {
+            if let Some(access) = self.batch.next() {
+                return Some(access);
+            }
+            self.batch = self.receiver.recv().ok()?.into_iter();
+        }
+    }
+}
+
+/// Run the parallel loops of `kernel` on `threads` simulated threads and call `visit` with the
+/// thread, reference ID, cache line and whether it stores, for every access.
+///
+/// The iterations of every parallel loop are divided among the threads by `schedule`, and code
+/// outside parallel loops is run by thread 0. The accesses are interleaved deterministically:
+/// the threads make one access each in turn, skipping those that are done, and all of them
+/// finish a parallel loop before any goes on, as at the barrier ending an OpenMP loop. Each
+/// thread walks the whole loop nest on its own OS thread and keeps its own iterations, so the
+/// trace is never held in memory.
+pub fn trace_threads(
+    kernel: &Kernel,
+    params: &[usize],
+    layout: &DataLayout,
+    threads: usize,
+    schedule: Schedule,
+    chunk: usize,
+    mut visit: impl FnMut(usize, usize, u64, bool),
+) {
+    thread::scope(|scope| {
+        let mut streams: Vec<ThreadStream> = (0..threads)
+            .map(|thread| {
+                let (sender, receiver) = mpsc::sync_channel(BUFFER);
+                scope.spawn(move || {
+                    // loop nests are not `Send`, so every thread builds its own
+                    let mut layout = layout.clone();
+                    let nest = kernel.build(params, &mut layout).unwrap();
+                    let mut batch = Vec::with_capacity(BATCH);
+                    let mut disconnected = false;
+                    nest.for_each_parallel_access(&layout, &mut |position, ref_id, addr, write| {
+                        let owner = match position.iteration {
+                            Some((index, count)) => schedule.owner(index, count, threads, chunk),
+                            None => 0,
+                        };
+                        if owner != thread || disconnected {
+                            return;
+                        }
+                        batch.push(ThreadAccess {
+                            region: position.region(),
+                            ref_id,
+                            addr,
+                            write,
+                        });
+                        if batch.len() == BATCH {
+                            disconnected = sender.send(mem::take(&mut batch)).is_err();
+                        }
+                    });
+                    if !batch.is_empty() && !disconnected {
+                        let _ = sender.send(batch);
+                    }
+                });
+                ThreadStream {
+                    receiver,
+                    batch: Vec::new().into_iter(),
+                }
+            })
+            .collect();
+
+        let mut heads: Vec<Option<ThreadAccess>> =
+            streams.iter_mut().map(|stream| stream.next()).collect();
+        while let Some(region) = heads.iter().flatten().map(|access| access.region).min() {
+            let mut running = true;
+            while running {
+                running = false;
+                for (thread, stream) in streams.iter_mut().enumerate() {
+                    match &heads[thread] {
+                        Some(access) if access.region == region => {
+                            visit(thread, access.ref_id, access.addr, access.write);
+                            heads[thread] = stream.next();
+                            running = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Trace the reuse intervals of `kernel` run on `threads` threads (see `trace_threads`).
+///
+/// `trace` gets the interleaved trace, whose reuse intervals are those of a cache shared by all
+/// threads, and `thread_traces` one trace per thread, whose reuse intervals and times are
+/// counted in the accesses of that thread alone, as seen by a private cache. All records carry
+/// their thread. Accesses by the references in `stores` are marked as writes. The histograms
+/// written to `hist_path` and `ref_hist_path` are those of the interleaved trace.
+#[allow(clippy::too_many_arguments)]
+pub fn tracing_parallel_ri(
+    kernel: &Kernel,
+    params: &[usize],
+    layout: &DataLayout,
+    threads: usize,
+    schedule: Schedule,
+    chunk: usize,
+    stores: &FxHashSet<usize>,
+    mut trace: Option<TraceWriter>,
+    mut thread_traces: Vec<TraceWriter>,
+    hist_path: &str,
+    ref_hist_path: &str,
+) -> Hist {
+    println!("Tracing Reuse Interval on {} threads...", threads);
+    let mut tracer = Tracer::new();
+    let mut thread_tracers: Vec<Tracer> = (0..threads).map(|_| Tracer::new()).collect();
+    trace_threads(
+        kernel,
+        params,
+        layout,
+        threads,
+        schedule,
+        chunk,
+        |thread, ref_id, addr, write| {
+            let write = write || stores.contains(&ref_id);
+            let mut record = tracer.access(ref_id, addr, write);
+            record.thread = Some(thread);
+            if let Some(trace) = &mut trace {
+                trace.write(&record).expect("Unable to write data");
+            }
+            let mut record = thread_tracers[thread].access(ref_id, addr, write);
+            record.thread = Some(thread);
+            if let Some(thread_trace) = thread_traces.get_mut(thread) {
+                thread_trace.write(&record).expect("Unable to write data");
+            }
+        },
+    );
+    for writer in trace.into_iter().chain(thread_traces) {
+        writer.finish().expect("Unable to write data");
+    }
+    for (thread, thread_tracer) in thread_tracers.iter().enumerate() {
+        println!("thread {}: {} accesses", thread, thread_tracer.time());
+    }
+    let (hist, ref_hists) = tracer.finish();
+
+    println!("Writing histogram...");
+    let mut hist_file = File::create(hist_path).expect("Unable to create hist file");
+    hist_file
+        .write_all(hist.to_string().as_bytes())
+        .expect("Unable to write hist data");
+    ref_hists
+        .write(ref_hist_path)
+        .expect("Unable to write histograms");
+
+    hist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernels::find_kernel;
+    use fxhash::FxHashMap;
+
+    /// The iterations of a loop of `count` iterations run by each thread.
+    fn iterations(
+        schedule: Schedule,
+        count: usize,
+        threads: usize,
+        chunk: usize,
+    ) -> Vec<Vec<usize>> {
+        let mut iterations = vec![Vec::new(); threads];
+        for index in 0..count {
+            iterations[schedule.owner(index, count, threads, chunk)].push(index);
+        }
+        iterations
+    }
+
+    #[test]
+    fn schedules_deal_iterations_to_threads() {
+        assert_eq!(
+            iterations(Schedule::Block, 10, 3, 2),
+            [vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+        assert_eq!(
+            iterations(Schedule::Block, 2, 4, 2),
+            [vec![0], vec![1], vec![], vec![]]
+        );
+        assert_eq!(
+            iterations(Schedule::Cyclic, 10, 3, 2),
+            [vec![0, 3, 6, 9], vec![1, 4, 7], vec![2, 5, 8]]
+        );
+        assert_eq!(
+            iterations(Schedule::Static, 10, 3, 2),
+            [vec![0, 1, 6, 7], vec![2, 3, 8, 9], vec![4, 5]]
+        );
+        // static chunks of one iteration are the cyclic schedule
+        assert_eq!(
+            iterations(Schedule::Static, 10, 3, 1),
+            iterations(Schedule::Cyclic, 10, 3, 2)
+        );
+    }
+
+    #[test]
+    fn interleaves_the_threads_until_the_barrier() {
+        let kernel = Kernel::from_text(
+            "copy",
+            "params n
+            array x[n], y[n]
+            y[0] =
+            parallel for i in 0..n {
+              x[i] = y[i]
+            }
+            y[1] =",
+        )
+        .unwrap();
+        // with one-byte elements and lines, x takes lines 0 to 2 and y lines 3 to 5
+        let layout = DataLayout::new(1, 1, FxHashMap::default());
+        let mut accesses = Vec::new();
+        trace_threads(
+            &kernel,
+            &[3],
+            &layout,
+            2,
+            Schedule::Block,
+            1,
+            |thread, ref_id, addr, write| accesses.push((thread, ref_id, addr, write)),
+        );
+        assert_eq!(
+            accesses,
+            [
+                // thread 0 runs the code before the loop
+                (0, 0, 3, true),
+                // then the threads take turns, thread 0 with iterations 0 and 1 and thread 1
+                // with iteration 2, which is done first
+                (0, 1, 3, false),
+                (1, 1, 5, false),
+                (0, 2, 0, true),
+                (1, 2, 2, true),
+                (0, 1, 4, false),
+                (0, 2, 1, true),
+                // and thread 0 runs the code after the loop once both are done
+                (0, 3, 4, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn runs_seidel_2d_on_one_thread() {
+        let kernel = find_kernel("seidel-2d").unwrap();
+        let mut layout = DataLayout::default();
+        let nest = kernel.build(&[2, 6], &mut layout).unwrap();
+        let mut serial = Vec::new();
+        nest.for_each_parallel_access(&layout, &mut |position, ref_id, addr, write| {
+            assert_eq!(position.parallel_loops, 0);
+            assert_eq!(position.iteration, None);
+            serial.push((0, ref_id, addr, write));
+        });
+
+        let mut accesses = Vec::new();
+        trace_threads(
+            &kernel,
+            &[2, 6],
+            &layout,
+            4,
+            Schedule::Cyclic,
+            1,
+            |thread, ref_id, addr, write| accesses.push((thread, ref_id, addr, write)),
+        );
+        assert_eq!(accesses, serial);
+    }
+}
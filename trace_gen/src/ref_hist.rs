@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Whether a reuse interval looks back to the previous access of the same line or forward to
+/// the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Forward => write!(f, "forward"),
+            Direction::Backward => write!(f, "backward"),
+        }
+    }
+}
+
+/// Reuse interval histograms kept separately for every reference.
+#[derive(Default)]
+pub struct RefHistograms {
+    /// for every reference, the number of its accesses seen and of those recorded
+    counts: BTreeMap<usize, (u64, u64)>,
+    /// for every reference and direction, the number of recorded accesses by reuse interval;
+    /// `None` counts cold misses (backward) or accesses that are not reused (forward)
+    histograms: BTreeMap<(usize, Direction), BTreeMap<Option<u64>, u64>>,
+}
+
+impl RefHistograms {
+    /// Count an access by `reference`, which may or may not be recorded.
+    pub fn access(&mut self, reference: usize) {
+        self.counts.entry(reference).or_default().0 += 1;
+    }
+
+    /// Count an access by `reference` whose reuse intervals are recorded.
+    pub fn record(&mut self, reference: usize) {
+        self.counts.entry(reference).or_default().1 += 1;
+    }
+
+    /// Add a reuse interval of an access by `reference`.
+    pub fn add(&mut self, reference: usize, direction: Direction, reuse_interval: Option<u64>) {
+        *self
+            .histograms
+            .entry((reference, direction))
+            .or_default()
+            .entry(reuse_interval)
+            .or_default() += 1;
+    }
+
+    /// The number of recorded accesses.
+    pub fn recorded(&self) -> u64 {
+        self.counts.values().map(|&(_, recorded)| recorded).sum()
+    }
+
+    /// Write the histograms as CSV, one `reference,direction,reuse_interval,count,sample_rate`
+    /// row per reference, direction and reuse interval, with the reference ID in hex as in the
+    /// trace. The reuse interval is empty for cold
+    /// misses (backward) and for accesses that are not reused (forward), and the sample rate is
+    /// the fraction of the reference's accesses that were recorded, by which the counts are
+    /// divided to estimate those of the full trace.
+    pub fn write(&self, file_path: &str) -> io::Result<()> {
+        if let Some(parent) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        writeln!(
+            writer,
+            "reference,direction,reuse_interval,count,sample_rate"
+        )?;
+        for ((reference, direction), histogram) in &self.histograms {
+            let (accesses, recorded) = self.counts[reference];
+            let sample_rate = recorded as f64 / accesses as f64;
+            for (reuse_interval, count) in histogram {
+                let reuse_interval = reuse_interval.map_or(String::new(), |ri| ri.to_string());
+                writeln!(
+                    writer,
+                    "{:08x},{},{},{},{}",
+                    reference, direction, reuse_interval, count, sample_rate
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
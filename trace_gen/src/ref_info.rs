@@ -0,0 +1,212 @@
+use dace::ast::{AryRef, Node, Stmt};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// The value given to every loop variable when probing a DACE subscript function, away from 0
+/// so that subscripts such as `i - 1` do not wrap.
+const PROBE: i32 = 64;
+
+/// Where a reference is in its loop nest, so that reports can name it.
+#[derive(Debug, Clone)]
+pub struct RefInfo {
+    pub reference: usize,
+    pub array: String,
+    /// the subscripts as written, e.g. `[i][k]`
+    pub subscripts: String,
+    /// the number of enclosing loops
+    pub depth: usize,
+    /// the IDs of the enclosing loops, outermost first; loops are numbered in program order
+    pub loops: Vec<usize>,
+}
+
+impl RefInfo {
+    /// The reference as written, e.g. `A[i][k]`.
+    pub fn label(&self) -> String {
+        format!("{}{}", self.array, self.subscripts)
+    }
+}
+
+/// Write the reference metadata as CSV, one `reference,array,subscripts,depth,loops` row per
+/// reference, with the reference ID in hex as in the trace and the loop IDs separated by spaces.
+pub fn write_ref_info(file_path: &str, refs: &[RefInfo]) -> io::Result<()> {
+    if let Some(parent) = Path::new(file_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    writeln!(writer, "reference,array,subscripts,depth,loops")?;
+    for info in refs {
+        let loops: Vec<String> = info.loops.iter().map(|id| id.to_string()).collect();
+        writeln!(
+            writer,
+            "{:08x},{},{},{},{}",
+            info.reference,
+            info.array,
+            info.subscripts,
+            info.depth,
+            loops.join(" ")
+        )?;
+    }
+    writer.flush()
+}
+
+/// The references of a DACE loop nest whose IDs have been assigned, sorted by ID.
+///
+/// DACE subscripts are closures, so they are recovered by evaluating them: a subscript that
+/// changes by the same amount whenever a loop variable is incremented is written as an affine
+/// expression of the loop variables, and any other as `?`.
+pub fn dace_references(code: &Rc<Node>) -> Vec<RefInfo> {
+    let mut refs = Vec::new();
+    collect_refs(code, &mut Vec::new(), &mut 0, &mut refs);
+    refs.sort_by_key(|info| info.reference);
+    refs
+}
+
+/// Collect the references under `code`, inside the loops `loops` (ID and loop variable).
+/// `next_loop` is the ID of the next loop in program order.
+fn collect_refs(
+    code: &Rc<Node>,
+    loops: &mut Vec<(usize, String)>,
+    next_loop: &mut usize,
+    refs: &mut Vec<RefInfo>,
+) {
+    match &code.stmt {
+        Stmt::Ref(ary_ref) => {
+            let ivs: Vec<&str> = loops.iter().map(|(_, iv)| iv.as_str()).collect();
+            refs.push(RefInfo {
+                reference: ary_ref.ref_id.unwrap(),
+                array: ary_ref.name.clone(),
+                subscripts: describe_subscripts(ary_ref, &ivs),
+                depth: loops.len(),
+                loops: loops.iter().map(|(id, _)| *id).collect(),
+            });
+        }
+        Stmt::Loop(aloop) => {
+            loops.push((*next_loop, aloop.iv.clone()));
+            *next_loop += 1;
+            for stmt in &aloop.body {
+                collect_refs(stmt, loops, next_loop, refs);
+            }
+            loops.pop();
+        }
+        Stmt::Block(blk) => {
+            for stmt in blk {
+                collect_refs(stmt, loops, next_loop, refs);
+            }
+        }
+        Stmt::Branch(stmt) => {
+            collect_refs(&stmt.then_body, loops, next_loop, refs);
+            if let Some(else_body) = &stmt.else_body {
+                collect_refs(else_body, loops, next_loop, refs);
+            }
+        }
+    }
+}
+
+/// The subscripts of `ary_ref` as expressions of the loop variables `ivs`, e.g. `[i][k+1]`.
+fn describe_subscripts(ary_ref: &AryRef, ivs: &[&str]) -> String {
+    let eval = |ivec: &[i32]| -> Vec<i64> {
+        (ary_ref.sub)(ivec)
+            .into_iter()
+            .map(|index| index as i64)
+            .collect()
+    };
+    let base = vec![PROBE; ivs.len()];
+    let shifted = |k: Option<usize>, by: i32| {
+        let mut ivec = base.clone();
+        match k {
+            Some(k) => ivec[k] += by,
+            None => ivec.iter_mut().for_each(|i| *i += by),
+        }
+        eval(&ivec)
+    };
+    let at_base = eval(&base);
+    let once: Vec<Vec<i64>> = (0..ivs.len()).map(|k| shifted(Some(k), 1)).collect();
+    let twice: Vec<Vec<i64>> = (0..ivs.len()).map(|k| shifted(Some(k), 2)).collect();
+    let all = shifted(None, 1);
+
+    at_base
+        .iter()
+        .enumerate()
+        .map(|(d, &value)| {
+            let coefficients: Vec<i64> = once.iter().map(|once| once[d] - value).collect();
+            // the steps along every variable are constant and add up
+            let affine = coefficients
+                .iter()
+                .zip(&twice)
+                .all(|(&coefficient, twice)| twice[d] - value == 2 * coefficient)
+                && all[d] - value == coefficients.iter().sum::<i64>();
+            if !affine {
+                return "[?]".to_string();
+            }
+            let constant = value - coefficients.iter().sum::<i64>() * PROBE as i64;
+            format!("[{}]", affine_expr(&coefficients, ivs, constant))
+        })
+        .collect()
+}
+
+/// `constant + sum(coefficients[k] * ivs[k])`, written without zero terms.
+fn affine_expr(coefficients: &[i64], ivs: &[&str], constant: i64) -> String {
+    let mut text = String::new();
+    for (&coefficient, iv) in coefficients.iter().zip(ivs) {
+        if coefficient == 0 {
+            continue;
+        }
+        if coefficient < 0 {
+            text.push('-');
+        } else if !text.is_empty() {
+            text.push('+');
+        }
+        if coefficient.abs() != 1 {
+            text += &format!("{}*", coefficient.abs());
+        }
+        text += iv;
+    }
+    if constant != 0 || text.is_empty() {
+        if constant < 0 {
+            text.push('-');
+        } else if !text.is_empty() {
+            text.push('+');
+        }
+        text += &constant.abs().to_string();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampling::assign_ref_id;
+    use dace_tests::polybench_simplify::_3mm;
+
+    #[test]
+    fn labels_3mm_references_with_their_loop_variables() {
+        let mut code = _3mm(8, 8, 8, 8, 8);
+        assign_ref_id(&mut code);
+        let refs = dace_references(&code);
+
+        let a = refs.iter().find(|info| info.array == "A").unwrap();
+        assert_eq!(a.label(), "A[i][k]");
+        assert_eq!(a.depth, 3);
+        let b = refs.iter().find(|info| info.array == "B").unwrap();
+        assert_eq!(b.label(), "B[k][j]");
+        assert_eq!(b.loops, a.loops);
+        // every subscript of 3mm is affine
+        assert!(refs.iter().all(|info| !info.subscripts.contains('?')));
+        assert!(refs
+            .iter()
+            .enumerate()
+            .all(|(id, info)| info.reference == id));
+    }
+
+    #[test]
+    fn writes_affine_expressions_without_zero_terms() {
+        let ivs = ["i", "j", "k"];
+        assert_eq!(affine_expr(&[1, 0, 0], &ivs, 0), "i");
+        assert_eq!(affine_expr(&[0, 1, -2], &ivs, 1), "j-2*k+1");
+        assert_eq!(affine_expr(&[-1, 0, 0], &ivs, -1), "-i-1");
+        assert_eq!(affine_expr(&[0, 0, 0], &ivs, 0), "0");
+        assert_eq!(affine_expr(&[], &[], 5), "5");
+    }
+}
@@ -1,9 +1,110 @@
-use dace::ast::AryRef;
+use dace::ast::{AryRef, Node, Stmt};
+use dace::iter::Walk;
+use fxhash::FxHashMap;
+use std::rc::Rc;
 
-const DS: usize = 8;
-const CLS: usize = 64;
+/// The default element size in bytes (doubles).
+pub const DS: usize = 8;
+/// The default cache line size in bytes.
+pub const CLS: usize = 64;
 
-pub fn access3addr(ary_ref: &AryRef, ivec: &[i32]) -> usize {
+/// How array elements are laid out in memory and grouped into cache lines.
+///
+/// Arrays are placed one after another in the order `set_arybase` gives them, each taking
+/// `element count * element size` bytes, so arrays with different element sizes do not overlap.
+#[derive(Clone)]
+pub struct DataLayout {
+    pub element_size: usize,
+    pub line_size: usize,
+    /// element sizes of the arrays that differ from `element_size`
+    pub array_element_sizes: FxHashMap<String, usize>,
+    /// the byte address of the first element of each array
+    bases: FxHashMap<String, usize>,
+}
+
+impl Default for DataLayout {
+    fn default() -> Self {
+        DataLayout::new(DS, CLS, FxHashMap::default())
+    }
+}
+
+impl DataLayout {
+    pub fn new(
+        element_size: usize,
+        line_size: usize,
+        array_element_sizes: FxHashMap<String, usize>,
+    ) -> DataLayout {
+        if element_size == 0 || line_size == 0 {
+            panic!("element and cache line sizes must be positive");
+        }
+        DataLayout {
+            element_size,
+            line_size,
+            array_element_sizes,
+            bases: FxHashMap::default(),
+        }
+    }
+
+    pub fn element_size_of(&self, array: &str) -> usize {
+        self.array_element_sizes
+            .get(array)
+            .copied()
+            .unwrap_or(self.element_size)
+    }
+
+    /// Compute the byte address of every array in `code`, whose element bases must already
+    /// have been set by `set_arybase`. If all arrays share one element size, the bases of
+    /// `set_arybase` are simply scaled by it.
+    pub fn place_arrays(&mut self, code: &Rc<Node>) {
+        self.bases.clear();
+        if self.array_element_sizes.is_empty() {
+            return;
+        }
+
+        let mut arrays: Vec<(usize, String, usize)> = Vec::new();
+        Walk::new(code).for_each(|node| {
+            if let Stmt::Ref(ary_ref) = &node.stmt {
+                if !arrays.iter().any(|(_, name, _)| *name == ary_ref.name) {
+                    let elements = ary_ref.dim.iter().product();
+                    arrays.push((ary_ref.base.unwrap(), ary_ref.name.clone(), elements));
+                }
+            }
+        });
+        arrays.sort();
+        self.place(
+            arrays
+                .into_iter()
+                .map(|(_, name, elements)| (name, elements)),
+        );
+    }
+
+    /// Place the given arrays, named with their element counts, one after another in that
+    /// order.
+    pub fn place(&mut self, arrays: impl IntoIterator<Item = (String, usize)>) {
+        self.bases.clear();
+        let mut next = 0;
+        for (name, elements) in arrays {
+            let size = elements * self.element_size_of(&name);
+            self.bases.insert(name, next);
+            next += size;
+        }
+    }
+
+    /// The cache line holding element `offset` of an array placed by `place`.
+    pub fn line_of(&self, array: &str, offset: usize) -> usize {
+        (self.bases[array] + offset * self.element_size_of(array)) / self.line_size
+    }
+
+    fn base_of(&self, ary_ref: &AryRef) -> usize {
+        match self.bases.get(&ary_ref.name) {
+            Some(&base) => base,
+            None => ary_ref.base.unwrap() * self.element_size,
+        }
+    }
+}
+
+/// The cache line accessed by `ary_ref` at iteration `ivec`.
+pub fn access3addr(ary_ref: &AryRef, ivec: &[i32], layout: &DataLayout) -> usize {
     let ary_index = (ary_ref.sub)(ivec);
     if ary_index.len() != ary_ref.dim.len() {
         panic!("array index and dimension do not match");
@@ -14,5 +115,5 @@ pub fn access3addr(ary_ref: &AryRef, ivec: &[i32]) -> usize {
         .zip(ary_ref.dim.iter())
         .fold(0, |acc, (&i, &d)| acc * d + i);
 
-    (ary_ref.base.unwrap() + offset) * DS / CLS
+    (layout.base_of(ary_ref) + offset * layout.element_size_of(&ary_ref.name)) / layout.line_size
 }
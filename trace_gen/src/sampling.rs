@@ -1,17 +1,17 @@
-use crate::ri_utils::access3addr;
-use dace::arybase::set_arybase;
-use dace::ast::{LoopBound, Node, Stmt};
+use crate::ref_hist::{Direction, RefHistograms};
+use crate::ri_utils::DataLayout;
+use crate::trace_writer::TraceWriter;
+use crate::tracer::{LoopNest, Tracer};
+use clap::ValueEnum;
+use dace::ast::{Node, Stmt};
 use dace::iter::Walk;
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use hist::Hist;
-use std::collections::hash_map::Entry;
-use std::fs;
+use rand::rngs::StdRng;
+use rand::Rng;
 use std::fs::File;
 use std::io::prelude::*;
 use std::rc::Rc;
-use std::sync::atomic::{AtomicI64, Ordering};
-
-static COUNTER: AtomicI64 = AtomicI64::new(0);
 
 pub fn assign_ref_id(node: &mut Rc<Node>) {
     println!("Assigning ID...");
@@ -63,110 +63,130 @@ pub fn print_tree(node: &Rc<Node>, level: usize) {
     }
 }
 
-pub fn tracing_ri(code: &mut Rc<Node>) -> Hist {
-    let mut hist = Hist::new();
-    let mut lat_hash: FxHashMap<String, FxHashMap<u64, i64>> = Default::default();
-    let mut csv = String::new();
-    // csv.push_str("Label\tReuse Interval\tTag\tLogical Time\n");
-    set_arybase(code);
-    assign_ref_id(code);
-    // print_tree(code, 0);
+/// Trace the reuse intervals of `nest`, streaming one record per access to `trace` if given.
+/// Accesses by the references in `stores` are marked as writes. The reuse interval histogram of
+/// all accesses is written to `hist_path`, and the forward and backward histograms of every
+/// reference to `ref_hist_path`.
+pub fn tracing_ri(
+    nest: &dyn LoopNest,
+    stores: &FxHashSet<usize>,
+    layout: &DataLayout,
+    mut trace: Option<TraceWriter>,
+    hist_path: &str,
+    ref_hist_path: &str,
+) -> Hist {
     println!("Tracing Reuse Interval...");
-    trace_ri(code, &mut lat_hash, &[], &mut hist, &mut csv);
-
-    println!("Writing to file...");
-    fs::create_dir_all("out").expect("Failed to create the output folder.");
-    let mut file = File::create("out/output.txt").expect("Unable to create file");
-    file.write_all(csv.as_bytes())
-        .expect("Unable to write data");
+    let mut tracer = Tracer::new();
+    tracer.trace(nest, layout, stores, |record| {
+        if let Some(trace) = &mut trace {
+            trace.write(record).expect("Unable to write data");
+        }
+    });
+    if let Some(trace) = trace {
+        trace.finish().expect("Unable to write data");
+    }
+    let (hist, ref_hists) = tracer.finish();
 
+    println!("Writing histogram...");
     let hist_data = hist.to_string();
-    let mut hist_file = File::create("out/hist_output.txt").expect("Unable to create hist file");
+    let mut hist_file = File::create(hist_path).expect("Unable to create hist file");
     hist_file
         .write_all(hist_data.as_bytes())
         .expect("Unable to write hist data");
+    ref_hists
+        .write(ref_hist_path)
+        .expect("Unable to write histograms");
 
     hist
 }
 
-fn trace_ri(
-    code: &Rc<Node>,
-    lat_hash: &mut FxHashMap<String, FxHashMap<u64, i64>>,
-    ivec: &[i32],
-    hist: &mut Hist,
-    csv: &mut String,
-) {
-    match &code.stmt {
-        Stmt::Ref(ary_ref) => {
-            let addr = access3addr(ary_ref, ivec) as u64;
-            let str_name = ary_ref.name.clone();
-            let mut prev_counter: Option<i64> = None;
-            let local_counter = COUNTER.load(Ordering::Relaxed);
+/// How `Sampler` chooses the accesses whose reuse it follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SamplingMethod {
+    /// every access independently, with probability equal to the sample rate
+    Random,
+    /// every access whose time is a multiple of the inverse of the sample rate
+    Periodic,
+}
 
-            match lat_hash.entry(str_name) {
-                Entry::Occupied(mut entry) => match entry.get_mut().entry(addr) {
-                    Entry::Occupied(mut inner) => {
-                        prev_counter = Some(inner.insert(local_counter));
-                    }
-                    Entry::Vacant(entry) => {
-                        entry.insert(local_counter);
-                    }
-                },
-                Entry::Vacant(entry) => {
-                    let mut inner_hash: FxHashMap<u64, i64> = Default::default();
-                    inner_hash.insert(addr, local_counter);
-                    entry.insert(inner_hash);
-                }
-            }
-            let mut ri: Option<_> = None;
-            if let Some(prev_counter) = prev_counter {
-                ri = Some((local_counter - prev_counter) as usize);
-            }
-            hist.add_dist(ri);
-            COUNTER.fetch_add(1, Ordering::Relaxed);
-            let ref_label = format!("{:08x}", ary_ref.ref_id.unwrap());
-            let reuse_interval = ri.map_or("ffffffff".to_string(), |ri| format!("{:08x}", ri));
+/// Collects the forward reuse intervals of a sample of the accesses, as SHEL and CLAM do for
+/// lease assignment: a sampled access waits for the next access to its address, and only the
+/// addresses of pending samples are remembered, so memory does not grow with the trace.
+pub struct Sampler {
+    method: SamplingMethod,
+    rate: f64,
+    period: u64,
+    rng: StdRng,
+    time: u64,
+    /// the reference and time of the sampled access each address is waiting on
+    pending: FxHashMap<u64, (usize, u64)>,
+    histograms: RefHistograms,
+}
 
-            let addr_str = format!("{:08x}", addr);
-            let local_counter_str = local_counter.to_string();
+impl Sampler {
+    /// Sample a fraction `rate` (in `(0, 1]`) of the accesses.
+    pub fn new(method: SamplingMethod, rate: f64, rng: StdRng) -> Sampler {
+        if !(rate > 0.0 && rate <= 1.0) {
+            panic!("the sample rate must be in (0, 1]");
+        }
+        Sampler {
+            method,
+            rate,
+            period: (1.0 / rate).round() as u64,
+            rng,
+            time: 0,
+            pending: FxHashMap::default(),
+            histograms: RefHistograms::default(),
+        }
+    }
 
-            let line = format!(
-                "{},{},{},{}\n",
-                ref_label, reuse_interval, addr_str, local_counter_str
+    pub fn access(&mut self, reference: usize, addr: u64) {
+        if let Some((sampled_ref, sampled_time)) = self.pending.remove(&addr) {
+            self.histograms.add(
+                sampled_ref,
+                Direction::Forward,
+                Some(self.time - sampled_time),
             );
-            csv.push_str(&line);
         }
-        Stmt::Loop(aloop) => {
-            let mut i = match &aloop.lb {
-                LoopBound::Fixed(lb) => *lb,
-                LoopBound::Dynamic(lb) => lb(ivec),
-                _ => panic!("Affine loop bound not supported"),
-            };
-            let ub = match &aloop.ub {
-                LoopBound::Fixed(ub) => *ub,
-                LoopBound::Dynamic(ub) => ub(ivec),
-                _ => panic!("Affine loop bound not supported"),
-            };
-
-            while (aloop.test)(i, ub) {
-                aloop.body.iter().for_each(|stmt| {
-                    let mut myvec = ivec.to_owned();
-                    myvec.push(i);
-                    trace_ri(stmt, lat_hash, &myvec, hist, csv)
-                });
-                i = (aloop.step)(i);
-            }
+        self.histograms.access(reference);
+        let sampled = match self.method {
+            SamplingMethod::Random => self.rng.gen_bool(self.rate),
+            SamplingMethod::Periodic => self.time.is_multiple_of(self.period),
+        };
+        if sampled {
+            self.histograms.record(reference);
+            self.pending.insert(addr, (reference, self.time));
         }
+        self.time += 1;
+    }
 
-        Stmt::Block(blk) => blk
-            .iter()
-            .for_each(|s| trace_ri(s, lat_hash, ivec, hist, csv)),
-        Stmt::Branch(stmt) => {
-            if (stmt.cond)(ivec) {
-                trace_ri(&stmt.then_body, lat_hash, ivec, hist, csv)
-            } else if let Some(else_body) = &stmt.else_body {
-                trace_ri(else_body, lat_hash, ivec, hist, csv)
-            }
+    /// The histograms of the sampled accesses; samples whose address was not accessed again
+    /// are counted as not reused.
+    pub fn finish(mut self) -> RefHistograms {
+        for (_, (reference, _)) in self.pending.drain() {
+            self.histograms.add(reference, Direction::Forward, None);
         }
+        self.histograms
     }
 }
+
+/// Follow the reuse of a sample of the accesses of `nest` instead of tracing all of them, and
+/// write the per-reference histograms of their forward reuse intervals to `ref_hist_path`.
+pub fn sample_ri(
+    nest: &dyn LoopNest,
+    layout: &DataLayout,
+    mut sampler: Sampler,
+    ref_hist_path: &str,
+) -> RefHistograms {
+    println!("Sampling Reuse Interval...");
+    nest.for_each_access(layout, &mut |ref_id, addr, _| sampler.access(ref_id, addr));
+    let accesses = sampler.time;
+    let histograms = sampler.finish();
+    println!("{} of {} accesses sampled", histograms.recorded(), accesses);
+
+    println!("Writing histograms...");
+    histograms
+        .write(ref_hist_path)
+        .expect("Unable to write histograms");
+    histograms
+}
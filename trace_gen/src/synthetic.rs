@@ -0,0 +1,506 @@
+use crate::dsl::is_identifier;
+use crate::ref_info::RefInfo;
+use crate::ri_utils::DataLayout;
+use crate::tracer::{LoopNest, Position};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// The synthetic access patterns, with their required and optional keys. Every pattern also
+/// takes `array`, `writes` and `seed`.
+pub const PATTERNS: &[(&str, &[&str], &[&str])] = &[
+    ("scan", &["size"], &[]),
+    ("cyclic", &["size", "accesses"], &[]),
+    ("stride", &["size", "stride", "accesses"], &[]),
+    ("random", &["size", "accesses"], &[]),
+    ("zipf", &["size", "accesses"], &["alpha"]),
+];
+
+const COMMON_KEYS: [&str; 3] = ["array", "writes", "seed"];
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// one pass over the array, so no element is reused
+    Scan,
+    /// passes over the array, a loop whose working set is the array
+    Cyclic,
+    /// every `stride`th element, wrapping around the array
+    Stride(usize),
+    /// elements chosen uniformly at random
+    Random,
+    /// elements chosen at random with a Zipfian distribution: element `k` is chosen with a
+    /// probability proportional to `1 / (k + 1)^alpha`
+    Zipf(f64),
+}
+
+/// One access pattern of a synthetic workload, made by its own reference.
+#[derive(Debug, Clone)]
+struct Pattern {
+    name: String,
+    kind: Kind,
+    array: String,
+    /// the number of elements the pattern ranges over
+    size: usize,
+    accesses: usize,
+    /// the fraction of the accesses that store
+    writes: f64,
+    seed: u64,
+    /// the stream of the seed the pattern draws from, its reference ID, so that patterns with
+    /// the same seed make different choices
+    stream: u64,
+}
+
+/// A synthetic workload: a sequence of phases, each a mix of access patterns whose accesses
+/// are interleaved in proportion to their counts, so that all patterns of a phase finish
+/// together.
+///
+/// Workloads are written as their phases separated by `;`, each made of patterns joined by
+/// `+`, e.g. `cyclic(size=1024,accesses=100000); zipf(size=65536,accesses=50000,alpha=0.9) +
+/// scan(size=65536)`. Every pattern is one reference, with IDs in the order of the text, and
+/// walks an array of `size` elements, named by `array` (patterns naming the same array share
+/// it) or else private to the pattern. `writes` is the fraction of its accesses that store,
+/// chosen at random, and `seed` seeds its random choices (default 0), so a workload always
+/// produces the same trace. Each pattern draws from its own stream of the seed, so two random
+/// patterns with the same seed do not choose the same elements.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    phases: Vec<Vec<Pattern>>,
+    /// the arrays and their sizes, in the order of the text
+    arrays: Vec<(String, usize)>,
+}
+
+impl Workload {
+    pub fn parse(text: &str) -> Result<Workload, String> {
+        let mut phases = Vec::new();
+        let mut count = 0;
+        for phase in text.split(';') {
+            let mut patterns = Vec::new();
+            for pattern in split_patterns(phase)? {
+                patterns.push(parse_pattern(pattern, count)?);
+                count += 1;
+            }
+            if patterns.is_empty() {
+                return Err(format!("empty phase in '{}'", text.trim()));
+            }
+            phases.push(patterns);
+        }
+
+        let mut arrays: Vec<(String, usize)> = Vec::new();
+        for pattern in phases.iter().flatten() {
+            match arrays.iter_mut().find(|(name, _)| *name == pattern.array) {
+                Some((_, size)) => *size = (*size).max(pattern.size),
+                None => arrays.push((pattern.array.clone(), pattern.size)),
+            }
+        }
+        Ok(Workload { phases, arrays })
+    }
+
+    /// Place the arrays of the workload in `layout`.
+    pub fn place_arrays(&self, layout: &mut DataLayout) {
+        layout.place(self.arrays.iter().cloned());
+    }
+}
+
+impl LoopNest for Workload {
+    /// The workload has no parallel loops, so all of it runs on thread 0.
+    fn for_each_parallel_access(
+        &self,
+        layout: &DataLayout,
+        visit: &mut dyn FnMut(Position, usize, u64, bool),
+    ) {
+        let mut ref_id = 0;
+        for patterns in &self.phases {
+            let mut generators: Vec<Generator> = patterns.iter().map(Generator::new).collect();
+            // the pattern furthest behind its share of the phase goes next
+            while let Some(index) = (0..generators.len())
+                .filter(|&index| generators[index].done < patterns[index].accesses)
+                .min_by(|&a, &b| {
+                    let progress = |index: usize| {
+                        (generators[index].done + 1) as f64 / patterns[index].accesses as f64
+                    };
+                    progress(a).total_cmp(&progress(b))
+                })
+            {
+                let (offset, write) = generators[index].next();
+                let address = layout.line_of(&patterns[index].array, offset) as u64;
+                visit(Position::default(), ref_id + index, address, write);
+            }
+            ref_id += patterns.len();
+        }
+    }
+
+    fn references(&self) -> Vec<RefInfo> {
+        self.phases
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(reference, pattern)| RefInfo {
+                reference,
+                array: pattern.array.clone(),
+                subscripts: format!("[{}]", pattern.name),
+                depth: 0,
+                loops: Vec::new(),
+            })
+            .collect()
+    }
+}
+
+/// Makes the accesses of one pattern.
+struct Generator<'a> {
+    pattern: &'a Pattern,
+    done: usize,
+    rng: ChaCha8Rng,
+    /// for Zipfian patterns, the cumulative probability of every element
+    cdf: Vec<f64>,
+}
+
+impl Generator<'_> {
+    fn new(pattern: &Pattern) -> Generator<'_> {
+        let cdf = match pattern.kind {
+            Kind::Zipf(alpha) => {
+                let mut total = 0.0;
+                let mut cdf: Vec<f64> = (0..pattern.size)
+                    .map(|k| {
+                        total += 1.0 / ((k + 1) as f64).powf(alpha);
+                        total
+                    })
+                    .collect();
+                cdf.iter_mut().for_each(|p| *p /= total);
+                cdf
+            }
+            _ => Vec::new(),
+        };
+        let mut rng = ChaCha8Rng::seed_from_u64(pattern.seed);
+        rng.set_stream(pattern.stream);
+        Generator {
+            pattern,
+            done: 0,
+            rng,
+            cdf,
+        }
+    }
+
+    /// The element of the next access and whether it stores.
+    fn next(&mut self) -> (usize, bool) {
+        let size = self.pattern.size;
+        let offset = match self.pattern.kind {
+            Kind::Scan | Kind::Cyclic => self.done % size,
+            Kind::Stride(stride) => self.done * stride % size,
+            Kind::Random => self.rng.gen_range(0..size),
+            Kind::Zipf(_) => {
+                let p: f64 = self.rng.gen();
+                self.cdf.partition_point(|&q| q < p).min(size - 1)
+            }
+        };
+        self.done += 1;
+        let write = self.pattern.writes > 0.0 && self.rng.gen_bool(self.pattern.writes);
+        (offset, write)
+    }
+}
+
+/// Split a phase into its patterns, at the `+` outside parentheses.
+fn split_patterns(phase: &str) -> Result<Vec<&str>, String> {
+    let mut patterns = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in phase.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err(format!("unbalanced ')' in '{}'", phase.trim())),
+            ')' => depth -= 1,
+            '+' if depth == 0 => {
+                patterns.push(phase[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    patterns.push(phase[start..].trim());
+    if patterns.len() == 1 && patterns[0].is_empty() {
+        return Ok(Vec::new());
+    }
+    match patterns.iter().find(|pattern| pattern.is_empty()) {
+        Some(_) => Err(format!("empty pattern in '{}'", phase.trim())),
+        None => Ok(patterns),
+    }
+}
+
+/// Parse `NAME(key=value,...)`, the pattern with ID `id`.
+fn parse_pattern(text: &str, id: usize) -> Result<Pattern, String> {
+    let (name, args) = text
+        .strip_suffix(')')
+        .and_then(|text| text.split_once('('))
+        .ok_or_else(|| format!("'{}' is not of the form PATTERN(KEY=VALUE,...)", text))?;
+    let name = name.trim();
+    let &(_, required, optional) = PATTERNS
+        .iter()
+        .find(|(pattern, _, _)| *pattern == name)
+        .ok_or_else(|| format!("unknown pattern '{}'", name))?;
+
+    let mut values: Vec<(&str, &str)> = Vec::new();
+    for arg in args.split(',').map(str::trim).filter(|arg| !arg.is_empty()) {
+        let (key, value) = arg
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| format!("{}: '{}' is not of the form KEY=VALUE", name, arg))?;
+        if !required.contains(&key) && !optional.contains(&key) && !COMMON_KEYS.contains(&key) {
+            return Err(format!("{} does not take '{}'", name, key));
+        }
+        if values.iter().any(|&(other, _)| other == key) {
+            return Err(format!("{}: '{}' is given twice", name, key));
+        }
+        values.push((key, value));
+    }
+    if let Some(key) = required
+        .iter()
+        .find(|key| !values.iter().any(|(other, _)| other == *key))
+    {
+        return Err(format!(
+            "{} takes {}, but '{}' is missing",
+            name,
+            required.join(", "),
+            key
+        ));
+    }
+    let value = |key: &str| values.iter().find(|&&(other, _)| other == key).map(|v| v.1);
+    let count = |key: &str| -> Result<Option<usize>, String> {
+        value(key)
+            .map(|value| {
+                value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or_else(|| {
+                        format!("{}: {} '{}' is not a positive number", name, key, value)
+                    })
+            })
+            .transpose()
+    };
+    let number = |key: &str| -> Result<Option<f64>, String> {
+        value(key)
+            .map(|value| {
+                value
+                    .parse::<f64>()
+                    .map_err(|_| format!("{}: {} '{}' is not a number", name, key, value))
+            })
+            .transpose()
+    };
+
+    let size = count("size")?.unwrap();
+    let kind = match name {
+        "scan" => Kind::Scan,
+        "cyclic" => Kind::Cyclic,
+        "stride" => Kind::Stride(count("stride")?.unwrap()),
+        "random" => Kind::Random,
+        "zipf" => match number("alpha")?.unwrap_or(1.0) {
+            alpha if alpha >= 0.0 => Kind::Zipf(alpha),
+            _ => return Err(format!("{}: alpha must not be negative", name)),
+        },
+        _ => unreachable!(),
+    };
+    let writes = number("writes")?.unwrap_or(0.0);
+    if !(0.0..=1.0).contains(&writes) {
+        return Err(format!("{}: writes must be a fraction in [0, 1]", name));
+    }
+    let array = match value("array") {
+        Some(array) if is_identifier(array) => array.to_string(),
+        Some(array) => return Err(format!("{}: '{}' is not an array name", name, array)),
+        // a name no identifier can take, so that it is not shared by accident
+        None => format!("{}#{}", name, id),
+    };
+    let seed = value("seed")
+        .map(|seed| {
+            seed.parse::<u64>()
+                .map_err(|_| format!("{}: seed '{}' is not a number", name, seed))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    Ok(Pattern {
+        name: name.to_string(),
+        kind,
+        array,
+        size,
+        accesses: match kind {
+            Kind::Scan => size,
+            _ => count("accesses")?.unwrap(),
+        },
+        writes,
+        seed,
+        stream: id as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fxhash::FxHashMap;
+
+    /// The accesses of `workload` as (reference, element, write), with one-byte elements and
+    /// lines so that every array element is its own line.
+    fn trace(workload: &Workload) -> Vec<(usize, u64, bool)> {
+        let mut layout = DataLayout::new(1, 1, FxHashMap::default());
+        workload.place_arrays(&mut layout);
+        let mut accesses = Vec::new();
+        workload.for_each_parallel_access(&layout, &mut |position, reference, address, write| {
+            assert_eq!(position.iteration, None);
+            accesses.push((reference, address, write));
+        });
+        accesses
+    }
+
+    #[test]
+    fn parses_phases_and_shares_named_arrays() {
+        let workload =
+            Workload::parse("cyclic(size=4, accesses=8, array=a) + scan(size=8,array=a); random(size=16,accesses=10,seed=3,writes=0.5)")
+                .unwrap();
+        assert_eq!(
+            workload.phases.iter().map(Vec::len).collect::<Vec<_>>(),
+            [2, 1]
+        );
+        assert_eq!(
+            workload.arrays,
+            [("a".to_string(), 8), ("random#2".to_string(), 16)]
+        );
+        let random = &workload.phases[1][0];
+        assert!(matches!(random.kind, Kind::Random));
+        assert_eq!((random.accesses, random.seed, random.writes), (10, 3, 0.5));
+        // a scan makes one access per element
+        assert_eq!(workload.phases[0][1].accesses, 8);
+        let labels: Vec<String> = workload.references().iter().map(RefInfo::label).collect();
+        assert_eq!(labels, ["a[cyclic]", "a[scan]", "random#2[random]"]);
+    }
+
+    #[test]
+    fn rejects_malformed_workloads() {
+        for (text, error) in [
+            ("", "empty phase"),
+            ("scan(size=4);", "empty phase"),
+            ("scan(size=4) +", "empty pattern"),
+            ("scan(size=4))", "unbalanced ')'"),
+            ("scan size=4", "is not of the form PATTERN(KEY=VALUE,...)"),
+            ("loop(size=4)", "unknown pattern 'loop'"),
+            ("scan(size)", "'size' is not of the form KEY=VALUE"),
+            ("scan(size=4,alpha=1)", "scan does not take 'alpha'"),
+            ("scan(size=4,size=8)", "'size' is given twice"),
+            ("stride(size=4,accesses=8)", "but 'stride' is missing"),
+            (
+                "cyclic(size=0,accesses=8)",
+                "size '0' is not a positive number",
+            ),
+            (
+                "random(size=4,accesses=-1)",
+                "accesses '-1' is not a positive number",
+            ),
+            (
+                "zipf(size=4,accesses=8,alpha=x)",
+                "alpha 'x' is not a number",
+            ),
+            (
+                "zipf(size=4,accesses=8,alpha=-1)",
+                "alpha must not be negative",
+            ),
+            (
+                "random(size=4,accesses=8,writes=2)",
+                "writes must be a fraction",
+            ),
+            ("scan(size=4,array=a[0])", "'a[0]' is not an array name"),
+            (
+                "random(size=4,accesses=8,seed=-1)",
+                "seed '-1' is not a number",
+            ),
+        ] {
+            match Workload::parse(text) {
+                Ok(_) => panic!("'{}' was accepted", text),
+                Err(message) => assert!(message.contains(error), "'{}': {}", text, message),
+            }
+        }
+    }
+
+    #[test]
+    fn seeds_reproduce_each_pattern_with_its_own_stream() {
+        let text = "random(size=1000,accesses=50,seed=7,writes=0.5); random(size=1000,accesses=50,seed=7,writes=0.5)";
+        let accesses = trace(&Workload::parse(text).unwrap());
+        assert_eq!(accesses, trace(&Workload::parse(text).unwrap()));
+
+        // the second pattern walks its own array, placed after the first
+        let elements = |reference: usize, base: u64| -> Vec<(u64, bool)> {
+            accesses
+                .iter()
+                .filter(|access| access.0 == reference)
+                .map(|&(_, address, write)| (address - base, write))
+                .collect()
+        };
+        assert_eq!(elements(0, 0).len(), 50);
+        assert_ne!(elements(0, 0), elements(1, 1000));
+        assert!(accesses.iter().any(|access| access.2));
+        assert!(accesses.iter().any(|access| !access.2));
+
+        let reseeded = trace(&Workload::parse(&text.replace("seed=7", "seed=8")).unwrap());
+        assert_ne!(accesses, reseeded);
+    }
+
+    #[test]
+    fn zipf_cdf_follows_the_harmonic_weights() {
+        let workload =
+            Workload::parse("zipf(size=4,accesses=8); zipf(size=4,accesses=8,alpha=0)").unwrap();
+        let harmonic = 1.0 + 1.0 / 2.0 + 1.0 / 3.0 + 1.0 / 4.0;
+        let expected = [
+            1.0 / harmonic,
+            1.5 / harmonic,
+            (1.5 + 1.0 / 3.0) / harmonic,
+            1.0,
+        ];
+        let cdf = Generator::new(&workload.phases[0][0]).cdf;
+        assert_eq!(cdf.len(), 4);
+        for (p, q) in cdf.iter().zip(expected) {
+            assert!((p - q).abs() < 1e-12, "{:?}", cdf);
+        }
+        // with alpha 0 every element is equally likely
+        let cdf = Generator::new(&workload.phases[1][0]).cdf;
+        for (p, q) in cdf.iter().zip([0.25, 0.5, 0.75, 1.0]) {
+            assert!((p - q).abs() < 1e-12, "{:?}", cdf);
+        }
+        // the accesses stay in the array and favor its first element
+        let accesses =
+            trace(&Workload::parse("zipf(size=64,accesses=1000,alpha=1.5,seed=1)").unwrap());
+        assert!(accesses.iter().all(|access| access.1 < 64));
+        let first = accesses.iter().filter(|access| access.1 == 0).count();
+        assert!(accesses.iter().all(|access| accesses
+            .iter()
+            .filter(|other| other.1 == access.1)
+            .count()
+            <= first));
+    }
+
+    #[test]
+    fn interleaves_the_patterns_of_a_phase_in_proportion() {
+        // arrays scan#0 at 0, cyclic#1 at 2 and stride#2 at 5
+        let workload = Workload::parse(
+            "scan(size=2) + cyclic(size=3,accesses=4); stride(size=4,stride=3,accesses=3)",
+        )
+        .unwrap();
+        let accesses: Vec<(usize, u64)> = trace(&workload)
+            .into_iter()
+            .map(|(reference, address, write)| {
+                assert!(!write);
+                (reference, address)
+            })
+            .collect();
+        assert_eq!(
+            accesses,
+            [
+                // the cyclic pattern makes two accesses for every one of the scan, and the
+                // earlier pattern goes first when both are as far along
+                (1, 2),
+                (0, 0),
+                (1, 3),
+                (1, 4),
+                (0, 1),
+                (1, 2),
+                // the next phase starts after both have finished
+                (2, 5),
+                (2, 8),
+                (2, 7),
+            ]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use clap::ValueEnum;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The first bytes of a binary trace.
+pub const BINARY_MAGIC: &[u8; 8] = b"CLAMTRC1";
+/// The first bytes of a binary trace with forward reuse intervals.
+pub const FORWARD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC2";
+/// The first bytes of a binary trace with thread IDs.
+pub const THREAD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC3";
+/// The first bytes of a binary trace with forward reuse intervals and thread IDs.
+pub const FORWARD_THREAD_BINARY_MAGIC: &[u8; 8] = b"CLAMTRC4";
+
+/// How trace records are encoded.
+///
+/// A binary trace is `BINARY_MAGIC` followed by fixed 33-byte records: reference, reuse
+/// interval (`u64::MAX` if the address is not reused), address and time as little-endian `u64`,
+/// then `b'R'` or `b'W'`. The optional columns, the forward reuse interval then the thread ID,
+/// come after the time and add 8 bytes each; which ones a trace has is given by its magic (see
+/// `Columns`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TraceFormat {
+    /// one `ref,ri,addr,time,type[,fri][,thread]` line per access
+    Text,
+    /// fixed-size little-endian records, read without parsing
+    Binary,
+}
+
+/// The optional columns of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Columns {
+    /// the forward reuse interval of every access
+    pub forward: bool,
+    /// the thread that made every access
+    pub thread: bool,
+}
+
+impl Columns {
+    /// The first bytes of a binary trace with these columns.
+    pub fn magic(self) -> &'static [u8; 8] {
+        match (self.forward, self.thread) {
+            (false, false) => BINARY_MAGIC,
+            (true, false) => FORWARD_BINARY_MAGIC,
+            (false, true) => THREAD_BINARY_MAGIC,
+            (true, true) => FORWARD_THREAD_BINARY_MAGIC,
+        }
+    }
+
+    /// The columns of a binary trace starting with `magic`, if it is one.
+    pub fn from_magic(magic: &[u8]) -> Option<Columns> {
+        [(false, false), (true, false), (false, true), (true, true)]
+            .into_iter()
+            .map(|(forward, thread)| Columns { forward, thread })
+            .find(|columns| magic.starts_with(columns.magic()))
+    }
+
+    /// The size of a binary record.
+    pub fn binary_size(self) -> usize {
+        33 + 8 * self.forward as usize + 8 * self.thread as usize
+    }
+}
+
+/// One access of the trace.
+pub struct TraceRecord {
+    pub reference: u64,
+    /// the time since the previous access to the line
+    pub reuse_interval: Option<u64>,
+    pub address: u64,
+    pub time: u64,
+    pub write: bool,
+    /// the time until the next access to the line, if known
+    pub forward_reuse_interval: Option<u64>,
+    /// the thread that made the access, in a multi-threaded trace
+    pub thread: Option<usize>,
+}
+
+impl TraceRecord {
+    /// Encode the record in the binary format into `bytes`, which must be
+    /// `columns.binary_size()` long.
+    pub fn encode(&self, bytes: &mut [u8], columns: Columns) {
+        bytes[0..8].copy_from_slice(&self.reference.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.reuse_interval.unwrap_or(u64::MAX).to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.address.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.time.to_le_bytes());
+        let mut next = 32;
+        if columns.forward {
+            let forward_reuse_interval = self.forward_reuse_interval.unwrap_or(u64::MAX);
+            bytes[next..next + 8].copy_from_slice(&forward_reuse_interval.to_le_bytes());
+            next += 8;
+        }
+        if columns.thread {
+            let thread = self.thread.unwrap_or(0) as u64;
+            bytes[next..next + 8].copy_from_slice(&thread.to_le_bytes());
+        }
+        bytes[bytes.len() - 1] = if self.write { b'W' } else { b'R' };
+    }
+
+    /// Decode a binary record.
+    pub fn decode(bytes: &[u8], columns: Columns) -> TraceRecord {
+        let field = |i: usize| u64::from_le_bytes(bytes[i * 8..i * 8 + 8].try_into().unwrap());
+        let interval = |i: usize| Some(field(i)).filter(|&ri| ri != u64::MAX);
+        let thread_field = 4 + columns.forward as usize;
+        TraceRecord {
+            reference: field(0),
+            reuse_interval: interval(1),
+            address: field(2),
+            time: field(3),
+            write: bytes[bytes.len() - 1] == b'W',
+            forward_reuse_interval: if columns.forward { interval(4) } else { None },
+            thread: columns.thread.then(|| field(thread_field) as usize),
+        }
+    }
+}
+
+/// Writes trace records to a file as they are produced.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    format: TraceFormat,
+    columns: Columns,
+}
+
+impl TraceWriter {
+    /// Create the trace file, and the directories leading to it if needed. The records carry
+    /// the optional `columns`.
+    pub fn create(
+        file_path: &str,
+        format: TraceFormat,
+        columns: Columns,
+    ) -> io::Result<TraceWriter> {
+        if let Some(parent) = Path::new(file_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(file_path)?);
+        if format == TraceFormat::Binary {
+            writer.write_all(columns.magic())?;
+        }
+        Ok(TraceWriter {
+            writer,
+            format,
+            columns,
+        })
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => {
+                let interval =
+                    |ri: Option<u64>| ri.map_or("ffffffff".to_string(), |ri| format!("{:08x}", ri));
+                write!(
+                    self.writer,
+                    "{:08x},{},{:08x},{},{}",
+                    record.reference,
+                    interval(record.reuse_interval),
+                    record.address,
+                    record.time,
+                    if record.write { "W" } else { "R" }
+                )?;
+                // a thread ID is always the seventh column, so the forward reuse interval is left
+                // empty if it is not known
+                if self.columns.forward {
+                    write!(self.writer, ",{}", interval(record.forward_reuse_interval))?;
+                } else if self.columns.thread {
+                    write!(self.writer, ",")?;
+                }
+                if self.columns.thread {
+                    write!(self.writer, ",{}", record.thread.unwrap_or(0))?;
+                }
+                writeln!(self.writer)
+            }
+            TraceFormat::Binary => {
+                let mut bytes = [0; 49];
+                let bytes = &mut bytes[..self.columns.binary_size()];
+                record.encode(bytes, self.columns);
+                self.writer.write_all(bytes)
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,211 @@
+use crate::ref_hist::{Direction, RefHistograms};
+use crate::ref_info::{dace_references, RefInfo};
+use crate::ri_utils::{access3addr, DataLayout};
+use crate::sampling::assign_ref_id;
+use crate::trace_writer::TraceRecord;
+use dace::arybase::set_arybase;
+use dace::ast::{AryRef, LoopBound, Node, Stmt};
+use fxhash::{FxHashMap, FxHashSet};
+use hist::Hist;
+use std::rc::Rc;
+
+/// Where an access is among the parallel loops of a loop nest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Position {
+    /// the number of parallel loops started so far, including the one the access is in
+    pub parallel_loops: usize,
+    /// the iteration of the parallel loop the access is in, counted from 0, and the number of
+    /// iterations of that loop, or `None` outside parallel loops
+    pub iteration: Option<(usize, usize)>,
+}
+
+impl Position {
+    /// The region of the program the access is in. Regions alternate between sequential code
+    /// and parallel loops, so threads synchronize between regions.
+    pub fn region(&self) -> usize {
+        2 * self.parallel_loops - self.iteration.is_some() as usize
+    }
+}
+
+/// A loop nest whose accesses can be traced.
+pub trait LoopNest {
+    /// Call `visit` with the reference ID, the cache line under `layout` and whether it stores,
+    /// for every access in program order.
+    fn for_each_access(&self, layout: &DataLayout, visit: &mut dyn FnMut(usize, u64, bool)) {
+        self.for_each_parallel_access(layout, &mut |_, ref_id, addr, write| {
+            visit(ref_id, addr, write)
+        });
+    }
+
+    /// Like `for_each_access`, also passing the position of every access among the parallel
+    /// loops, whose iterations may be run by different threads. Which loops are parallel is up
+    /// to the loop nest; loops nested in a parallel loop run in its iterations.
+    fn for_each_parallel_access(
+        &self,
+        layout: &DataLayout,
+        visit: &mut dyn FnMut(Position, usize, u64, bool),
+    );
+
+    /// Where every reference is in the loop nest, sorted by ID.
+    fn references(&self) -> Vec<RefInfo>;
+}
+
+/// A DACE loop nest, whose stores and parallel loops are not known, so it runs on one thread.
+pub struct DaceLoopNest {
+    code: Rc<Node>,
+}
+
+impl DaceLoopNest {
+    /// Give the arrays of `code` their addresses under `layout` and its references their IDs,
+    /// so that it can be traced.
+    pub fn new(mut code: Rc<Node>, layout: &mut DataLayout) -> DaceLoopNest {
+        set_arybase(&mut code);
+        layout.place_arrays(&code);
+        assign_ref_id(&mut code);
+        DaceLoopNest { code }
+    }
+}
+
+impl LoopNest for DaceLoopNest {
+    fn for_each_parallel_access(
+        &self,
+        layout: &DataLayout,
+        visit: &mut dyn FnMut(Position, usize, u64, bool),
+    ) {
+        visit_refs(&self.code, &[], &mut |ary_ref, ivec| {
+            let addr = access3addr(ary_ref, ivec, layout) as u64;
+            visit(Position::default(), ary_ref.ref_id.unwrap(), addr, false);
+        });
+    }
+
+    fn references(&self) -> Vec<RefInfo> {
+        dace_references(&self.code)
+    }
+}
+
+/// Computes the reuse intervals of a stream of accesses.
+///
+/// A tracer owns its logical time and the last access to every cache line, so any number of
+/// programs can be traced in one process, one tracer each. Lines are identified by address
+/// alone, so accesses to different arrays that share a line reuse it.
+pub struct Tracer {
+    time: u64,
+    /// the time and reference of the last access to every line
+    last_access: FxHashMap<u64, (u64, usize)>,
+    hist: Hist,
+    ref_hists: RefHistograms,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new()
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Tracer {
+        Tracer {
+            time: 0,
+            last_access: FxHashMap::default(),
+            hist: Hist::new(),
+            ref_hists: RefHistograms::default(),
+        }
+    }
+
+    /// The number of accesses traced so far.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Trace an access by `reference` to the line `address`.
+    pub fn access(&mut self, reference: usize, address: u64, write: bool) -> TraceRecord {
+        let reuse_interval = match self.last_access.insert(address, (self.time, reference)) {
+            Some((prev_time, prev_ref)) => {
+                let ri = self.time - prev_time;
+                self.ref_hists.add(prev_ref, Direction::Forward, Some(ri));
+                Some(ri)
+            }
+            None => None,
+        };
+        self.hist.add_dist(reuse_interval.map(|ri| ri as usize));
+        self.ref_hists.access(reference);
+        self.ref_hists.record(reference);
+        self.ref_hists
+            .add(reference, Direction::Backward, reuse_interval);
+
+        let record = TraceRecord {
+            reference: reference as u64,
+            reuse_interval,
+            address,
+            time: self.time,
+            write,
+            forward_reuse_interval: None,
+            thread: None,
+        };
+        self.time += 1;
+        record
+    }
+
+    /// Trace every access of `nest`, which must have been built with `layout`, and pass its
+    /// record to `visit`. Accesses by the references in `stores` are marked as writes, as well
+    /// as those the loop nest knows to store.
+    pub fn trace(
+        &mut self,
+        nest: &dyn LoopNest,
+        layout: &DataLayout,
+        stores: &FxHashSet<usize>,
+        mut visit: impl FnMut(&TraceRecord),
+    ) {
+        nest.for_each_access(layout, &mut |ref_id, addr, write| {
+            let record = self.access(ref_id, addr, write || stores.contains(&ref_id));
+            visit(&record);
+        });
+    }
+
+    /// The reuse interval histogram of all accesses and the forward and backward histograms of
+    /// every reference. The last access to every line is counted as not reused.
+    pub fn finish(mut self) -> (Hist, RefHistograms) {
+        for (_, (_, reference)) in self.last_access.drain() {
+            self.ref_hists.add(reference, Direction::Forward, None);
+        }
+        (self.hist, self.ref_hists)
+    }
+}
+
+/// Call `visit` with every array reference executed by `code` and its iteration vector.
+fn visit_refs(code: &Rc<Node>, ivec: &[i32], visit: &mut impl FnMut(&AryRef, &[i32])) {
+    match &code.stmt {
+        Stmt::Ref(ary_ref) => visit(ary_ref, ivec),
+        Stmt::Loop(aloop) => {
+            let mut i = loop_bound(&aloop.lb, ivec);
+            let ub = loop_bound(&aloop.ub, ivec);
+
+            while (aloop.test)(i, ub) {
+                aloop.body.iter().for_each(|stmt| {
+                    let mut myvec = ivec.to_owned();
+                    myvec.push(i);
+                    visit_refs(stmt, &myvec, visit)
+                });
+                i = (aloop.step)(i);
+            }
+        }
+
+        Stmt::Block(blk) => blk.iter().for_each(|s| visit_refs(s, ivec, visit)),
+        Stmt::Branch(stmt) => {
+            if (stmt.cond)(ivec) {
+                visit_refs(&stmt.then_body, ivec, visit)
+            } else if let Some(else_body) = &stmt.else_body {
+                visit_refs(else_body, ivec, visit)
+            }
+        }
+    }
+}
+
+/// The value of a loop bound at iteration `ivec` of the enclosing loops.
+fn loop_bound(bound: &LoopBound, ivec: &[i32]) -> i32 {
+    match bound {
+        LoopBound::Fixed(value) => *value,
+        LoopBound::Dynamic(bound) => bound(ivec),
+        _ => panic!("Affine loop bound not supported"),
+    }
+}